@@ -1,7 +1,17 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::str::FromStr;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, Rgba, RgbaImage};
+use imageproc::drawing::draw_filled_rect_mut;
+use imageproc::rect::Rect;
+
+fn read_input(path: Option<&str>) -> Vec<String> {
+    let input = match path {
+        Some(path) => std::fs::read_to_string(path).expect("Expected readable input file"),
+        None => include_str!("../input").to_string(),
+    };
 
-fn read_input() -> Vec<String> {
-    let input = include_str!("../input");
     input
         .lines()
         .map(std::string::ToString::to_string)
@@ -9,95 +19,976 @@ fn read_input() -> Vec<String> {
 }
 
 
+/// Rolls of paper in an `N`-dimensional grid spanning `0..=max[axis]` on every
+/// axis. With `wrap` set, every axis wraps around as on a torus.
 #[derive(Clone)]
-struct Map {
-    paper: HashSet<Coord>,
-    max_x: i32,
-    max_y: i32,
+struct Map<const N: usize> {
+    paper: HashSet<Coord<N>>,
+    max: Coord<N>,
+    wrap: bool,
 }
 
-type Coord = (i32, i32);
+type Coord<const N: usize> = [i32; N];
 
-fn parse(lines: &[String]) -> Map {
-    let mut map = HashSet::new();
+/// Which cells around a roll count as its neighbours.
+#[derive(Clone, Copy, Debug)]
+enum Neighbourhood {
+    /// The 4 orthogonally adjacent cells.
+    VonNeumann,
+    /// The 8 surrounding cells, diagonals included.
+    Moore,
+    /// All cells within the given Chebyshev (king move) distance.
+    Chebyshev(i32),
+    /// All cells within the given Manhattan distance.
+    Manhattan(i32),
+}
 
-    let max_y = (lines.len() - 1) as i32;
-    let max_x = (lines.first().unwrap().len() - 1) as i32;
+impl Neighbourhood {
+    fn radius(self) -> i32 {
+        match self {
+            Neighbourhood::VonNeumann | Neighbourhood::Moore => 1,
+            Neighbourhood::Chebyshev(r) | Neighbourhood::Manhattan(r) => r,
+        }
+    }
 
-    for (row, line) in lines.iter().enumerate() {
-        for (col, char) in line.chars().enumerate() {
-            if char == '@' {
-                map.insert((col as i32, row as i32));
+    /// All non-zero offsets in the neighbourhood, in `N` dimensions. Moore has
+    /// 8 offsets in 2D and 26 in 3D.
+    fn displacements<const N: usize>(self) -> Vec<Coord<N>> {
+        let radius = self.radius();
+        let distance: fn(&Coord<N>) -> i32 = match self {
+            Neighbourhood::VonNeumann | Neighbourhood::Manhattan(_) => {
+                |offset| offset.iter().map(|d| d.abs()).sum()
+            }
+            Neighbourhood::Moore | Neighbourhood::Chebyshev(_) => {
+                |offset| offset.iter().map(|d| d.abs()).max().unwrap_or(0)
+            }
+        };
+
+        let mut displacements = vec![[0; N]];
+        for axis in 0..N {
+            displacements = displacements.into_iter()
+                .flat_map(|offset| {
+                    (-radius..=radius).map(move |d| {
+                        let mut offset = offset;
+                        offset[axis] = d;
+                        offset
+                    })
+                })
+                .collect();
+        }
+
+        displacements.retain(|offset| (1..=radius).contains(&distance(offset)));
+        displacements
+    }
+}
+
+impl FromStr for Neighbourhood {
+    type Err = String;
+
+    /// Parses `von-neumann`, `moore`, `chebyshev:R` or `manhattan:R`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, radius) = match s.split_once(':') {
+            Some((kind, radius)) => {
+                let radius = radius.parse::<i32>().map_err(|_| format!("Invalid radius '{radius}'"))?;
+                if radius < 1 {
+                    return Err(format!("Radius must be at least 1, got {radius}"));
+                }
+                (kind, Some(radius))
             }
+            None => (s, None),
+        };
+
+        match (kind, radius) {
+            ("von-neumann", None) => Ok(Neighbourhood::VonNeumann),
+            ("moore", None) => Ok(Neighbourhood::Moore),
+            ("chebyshev", Some(r)) => Ok(Neighbourhood::Chebyshev(r)),
+            ("manhattan", Some(r)) => Ok(Neighbourhood::Manhattan(r)),
+            _ => Err(format!("Unknown neighbourhood '{s}'")),
         }
     }
+}
+
+/// When a roll counts as accessible, based on how many neighbouring rolls it has.
+#[derive(Clone, Copy, Debug)]
+enum Threshold {
+    LessThan(usize),
+    AtMost(usize),
+    Exactly(usize),
+    AtLeast(usize),
+    MoreThan(usize),
+}
+
+impl Threshold {
+    fn accepts(self, count: usize) -> bool {
+        match self {
+            Threshold::LessThan(n) => count < n,
+            Threshold::AtMost(n) => count <= n,
+            Threshold::Exactly(n) => count == n,
+            Threshold::AtLeast(n) => count >= n,
+            Threshold::MoreThan(n) => count > n,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
 
-    Map {
-        paper: map,
-        max_x,
-        max_y,
+    /// Parses a comparison followed by a count, e.g. `<4`, `<=3`, `=2`, `>=5` or `>1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| c.is_ascii_digit()).ok_or(format!("Expected a count in '{s}'"))?;
+        let (comparison, count) = s.split_at(split);
+        let count = count.parse().map_err(|_| format!("Invalid count '{count}'"))?;
+
+        match comparison {
+            "<" => Ok(Threshold::LessThan(count)),
+            "<=" => Ok(Threshold::AtMost(count)),
+            "=" | "==" => Ok(Threshold::Exactly(count)),
+            ">=" => Ok(Threshold::AtLeast(count)),
+            ">" => Ok(Threshold::MoreThan(count)),
+            _ => Err(format!("Unknown comparison '{comparison}'")),
+        }
     }
 }
 
-fn get_neighbours(map: &Map, (x, y): &Coord) -> Vec<Coord> {
-    let mut neighbours = vec![];
+/// The accessibility rule: a roll is accessible when its neighbour count in
+/// `neighbourhood` satisfies `threshold`.
+#[derive(Clone, Copy, Debug)]
+struct Rules {
+    neighbourhood: Neighbourhood,
+    threshold: Threshold,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            neighbourhood: Neighbourhood::Moore,
+            threshold: Threshold::LessThan(4),
+        }
+    }
+}
 
-    let displacements = vec![ (0, 1), (0, -1), (1, 0), (-1, 0), (1, 1), (-1, -1), (-1, 1), (1, -1) ];
-    for (dx, dy) in displacements {
-        let (nx, ny) = (x + dx, y + dy);
+/// Parses stacked 2D layers into an `N`-dimensional map. A single blank line
+/// starts the next layer along the third axis, two blank lines the next block
+/// along the fourth axis, and so on.
+fn parse<const N: usize>(lines: &[String], wrap: bool) -> Map<N> {
+    let mut paper = HashSet::new();
+    let mut max = [0; N];
+    let mut pos = [0; N];
+    let mut blank_lines = 0;
+    let mut seen_line = false;
 
-        if nx < 0 || ny < 0 || nx > map.max_x || ny > map.max_y {
+    for line in lines {
+        if line.trim().is_empty() {
+            blank_lines += 1;
             continue;
         }
 
-        if map.paper.contains(&(nx, ny)) {
-            neighbours.push((nx, ny));
+        // Blank lines before the first row don't separate layers, but a
+        // layer without any rolls still counts.
+        if blank_lines > 0 && seen_line {
+            let axis = blank_lines + 1;
+            assert!(axis < N, "Expected at most {} blank lines between layers of a {N}D map", N.saturating_sub(2));
+
+            pos[axis] += 1;
+            pos[1..axis].fill(0);
         }
+        blank_lines = 0;
+        seen_line = true;
+
+        for (col, char) in line.chars().enumerate() {
+            pos[0] = col as i32;
+            if char == '@' {
+                paper.insert(pos);
+            }
+        }
+
+        pos[0] = line.len() as i32 - 1;
+        for (max, pos) in max.iter_mut().zip(pos) {
+            *max = (*max).max(pos);
+        }
+
+        pos[1] += 1;
     }
 
-    neighbours
+    Map { paper, max, wrap }
 }
 
+impl<const N: usize> Map<N> {
+    /// The neighbourhood's offsets, checked against the map size: when
+    /// wrapping, two offsets must never land on the same cell.
+    fn displacements(&self, neighbourhood: Neighbourhood) -> Vec<Coord<N>> {
+        if self.wrap {
+            let radius = neighbourhood.radius();
+            assert!(
+                self.max.iter().all(|max| max + 1 > 2 * radius),
+                "Every axis must be longer than {} cells to wrap a radius {radius} neighbourhood",
+                2 * radius
+            );
+        }
+
+        neighbourhood.displacements()
+    }
+}
+
+fn neighbour_cells<'a, const N: usize>(map: &'a Map<N>, displacements: &'a [Coord<N>], pos: &Coord<N>) -> impl Iterator<Item = Coord<N>> + 'a {
+    let pos = *pos;
+
+    displacements.iter()
+        .filter_map(move |offset| {
+            let mut neighbour = pos;
+            for ((value, d), max) in neighbour.iter_mut().zip(offset).zip(map.max) {
+                *value += d;
+
+                if map.wrap {
+                    *value = value.rem_euclid(max + 1);
+                } else if !(0..=max).contains(value) {
+                    return None;
+                }
+            }
 
-fn part1(map: &Map) -> usize {
-    let fewer_than_four_neighbours: Vec<_> =
+            Some(neighbour)
+        })
+}
+
+fn get_neighbours<const N: usize>(map: &Map<N>, displacements: &[Coord<N>], pos: &Coord<N>) -> Vec<Coord<N>> {
+    neighbour_cells(map, displacements, pos)
+        .filter(|neighbour| map.paper.contains(neighbour))
+        .collect()
+}
+
+fn part1<const N: usize>(map: &Map<N>, rules: &Rules) -> usize {
+    let displacements = map.displacements(rules.neighbourhood);
+
+    let accessible: Vec<_> =
         map.paper.iter()
             .filter(|pos| {
-                get_neighbours(map, pos).len() < 4
+                rules.threshold.accepts(get_neighbours(map, &displacements, pos).len())
             }).collect();
 
-    // println!("{:?}", fewer_than_four_neighbours);
-
-    fewer_than_four_neighbours.len()
+    accessible.len()
 }
 
-fn remove_available_rolls(map: &mut Map) -> bool {
+fn remove_available_rolls<const N: usize>(map: &mut Map<N>, rules: &Rules, displacements: &[Coord<N>]) -> bool {
     let map_copy = map.clone();
     map.paper.retain(|pos| {
-        get_neighbours(&map_copy, pos).len() >= 4
+        !rules.threshold.accepts(get_neighbours(&map_copy, displacements, pos).len())
     });
 
     map_copy.paper.len() != map.paper.len()
 }
 
-fn part2(map: &Map) -> usize {
+/// Reference implementation of `part2`: rechecks every roll each round until
+/// nothing changes. Kept to cross-check `removal_waves` with `--verify`.
+fn part2_rounds<const N: usize>(map: &Map<N>, rules: &Rules) -> usize {
+    let displacements = map.displacements(rules.neighbourhood);
     let mut map_copy = map.clone();
 
     loop {
-        if !remove_available_rolls(&mut map_copy) {
+        if !remove_available_rolls(&mut map_copy, rules, &displacements) {
             break;
         }
     }
 
     map.paper.len() - map_copy.paper.len()
-    // println!("{:?}", fewer_than_four_neighbours);
+}
+
+/// Removes accessible rolls in waves, exactly like `part2_rounds`, but only
+/// re-examines rolls whose neighbour count changed in the previous wave.
+///
+/// A roll's accessibility depends only on its neighbour count, and every roll
+/// left after a wave was inaccessible before it, so only the neighbours of
+/// removed rolls can become accessible in the next wave.
+fn removal_waves<const N: usize>(map: &Map<N>, rules: &Rules) -> Vec<Vec<Coord<N>>> {
+    let displacements = map.displacements(rules.neighbourhood);
+
+    let mut counts: HashMap<Coord<N>, usize> = map.paper.iter()
+        .map(|pos| (*pos, get_neighbours(map, &displacements, pos).len()))
+        .collect();
+
+    let mut worklist: Vec<Coord<N>> = map.paper.iter().copied().collect();
+    let mut waves = vec![];
+
+    loop {
+        let wave: Vec<Coord<N>> = worklist.drain(..)
+            .filter(|pos| counts.get(pos).is_some_and(|count| rules.threshold.accepts(*count)))
+            .collect();
+
+        if wave.is_empty() {
+            break;
+        }
+
+        for pos in &wave {
+            counts.remove(pos);
+        }
+
+        let mut queued = HashSet::new();
+        for pos in &wave {
+            for neighbour in neighbour_cells(map, &displacements, pos) {
+                if let Some(count) = counts.get_mut(&neighbour) {
+                    *count -= 1;
+
+                    if queued.insert(neighbour) {
+                        worklist.push(neighbour);
+                    }
+                }
+            }
+        }
+
+        waves.push(wave);
+    }
+
+    waves
+}
+
+/// The number of rolls removed over all of `removal_waves`.
+fn part2<const N: usize>(waves: &[Vec<Coord<N>>]) -> usize {
+    waves.iter().map(Vec::len).sum()
+}
+
+/// A flat 2D map packed one bit per cell, 64 cells per word, row by row.
+/// Neighbour counts are computed for 64 cells at once by shifting whole rows
+/// and adding them into bit-sliced counters, so large maps never touch a hash
+/// set.
+struct BitGrid {
+    height: usize,
+    words_per_row: usize,
+    bits: Vec<u64>,
+}
+
+impl BitGrid {
+    fn parse(lines: &[String]) -> Self {
+        let width = lines.iter().map(String::len).max().unwrap_or(0);
+        let height = lines.len();
+        let words_per_row = width.div_ceil(64);
+        let mut bits = vec![0; words_per_row * height];
+
+        for (y, line) in lines.iter().enumerate() {
+            for (x, char) in line.bytes().enumerate() {
+                if char == b'@' {
+                    bits[y * words_per_row + x / 64] |= 1 << (x % 64);
+                }
+            }
+        }
+
+        BitGrid {
+            height,
+            words_per_row,
+            bits,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.bits.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    fn word(&self, y: isize, w: isize) -> u64 {
+        if y < 0 || y >= self.height as isize || w < 0 || w >= self.words_per_row as isize {
+            0
+        } else {
+            self.bits[y as usize * self.words_per_row + w as usize]
+        }
+    }
+
+    /// Word `w` of row `y`, shifted so that bit `i` holds the cell at
+    /// `(64 * w + i + dx, y)`. Cells outside the grid read as empty.
+    fn shifted_word(&self, y: isize, w: usize, dx: i32) -> u64 {
+        let w = w as isize + dx.div_euclid(64) as isize;
+        let shift = dx.rem_euclid(64);
+
+        if shift == 0 {
+            self.word(y, w)
+        } else {
+            (self.word(y, w) >> shift) | (self.word(y, w + 1) << (64 - shift))
+        }
+    }
+
+    /// The accessible rolls among the 64 cells of word `w` in row `y`.
+    fn accessible_word(&self, y: usize, w: usize, displacements: &[Coord<2>], accepted: &[usize]) -> u64 {
+        let rolls = self.bits[y * self.words_per_row + w];
+        if rolls == 0 {
+            return 0;
+        }
+
+        // planes[b] holds bit b of every cell's neighbour count.
+        let mut planes = [0u64; usize::BITS as usize];
+        let plane_count = (usize::BITS - displacements.len().leading_zeros()) as usize;
+
+        for [dx, dy] in displacements {
+            let mut carry = self.shifted_word(y as isize + *dy as isize, w, *dx);
+            for plane in &mut planes[..plane_count] {
+                let next_carry = *plane & carry;
+                *plane ^= carry;
+                carry = next_carry;
+            }
+        }
+
+        let mut mask = 0;
+        for count in accepted {
+            mask |= planes[..plane_count]
+                .iter()
+                .enumerate()
+                .fold(!0, |equal, (b, plane)| equal & if count >> b & 1 == 1 { *plane } else { !*plane });
+        }
+
+        rolls & mask
+    }
+
+    /// The neighbour counts the threshold accepts, given the neighbourhood size.
+    fn accepted_counts(rules: &Rules, displacements: &[Coord<2>]) -> Vec<usize> {
+        (0..=displacements.len())
+            .filter(|count| rules.threshold.accepts(*count))
+            .collect()
+    }
+
+    fn part1(&self, rules: &Rules) -> usize {
+        let displacements = rules.neighbourhood.displacements::<2>();
+        let accepted = Self::accepted_counts(rules, &displacements);
+
+        (0..self.height)
+            .flat_map(|y| (0..self.words_per_row).map(move |w| (y, w)))
+            .map(|(y, w)| self.accessible_word(y, w, &displacements, &accepted).count_ones() as usize)
+            .sum()
+    }
+
+    /// Removes accessible rolls round by round, like `part2_rounds`. Only rows
+    /// within reach of a row that changed in the previous round are rechecked.
+    fn part2(&self, rules: &Rules) -> usize {
+        let displacements = rules.neighbourhood.displacements::<2>();
+        let accepted = Self::accepted_counts(rules, &displacements);
+        let reach = displacements.iter().map(|[_, dy]| dy.unsigned_abs() as usize).max().unwrap_or(0);
+
+        let mut grid = BitGrid {
+            height: self.height,
+            words_per_row: self.words_per_row,
+            bits: self.bits.clone(),
+        };
+        let mut dirty = vec![true; self.height];
+
+        loop {
+            let removals: Vec<(usize, Vec<u64>)> = (0..grid.height)
+                .filter(|y| dirty[*y])
+                .map(|y| {
+                    let words = (0..grid.words_per_row)
+                        .map(|w| grid.accessible_word(y, w, &displacements, &accepted))
+                        .collect();
+                    (y, words)
+                })
+                .filter(|(_, words): &(usize, Vec<u64>)| words.iter().any(|word| *word != 0))
+                .collect();
+
+            if removals.is_empty() {
+                break;
+            }
+
+            dirty.fill(false);
+            for (y, words) in removals {
+                let row = &mut grid.bits[y * grid.words_per_row..(y + 1) * grid.words_per_row];
+                for (word, removed) in row.iter_mut().zip(words) {
+                    *word &= !removed;
+                }
+
+                dirty[y.saturating_sub(reach)..=(y + reach).min(grid.height - 1)].fill(true);
+            }
+        }
+
+        self.len() - grid.len()
+    }
+}
+
+/// How to draw each wave in `LayerMap::render`.
+#[derive(Clone, Copy, Debug)]
+enum LayerStyle {
+    /// One character per wave: `1`-`9`, then `a`-`z`, then `+`.
+    Digits,
+    /// The digit style, on an ANSI background colour that cycles per wave.
+    Colour,
+}
+
+impl FromStr for LayerStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "digits" => Ok(LayerStyle::Digits),
+            "colour" | "color" => Ok(LayerStyle::Colour),
+            _ => Err(format!("Unknown layer style '{s}'")),
+        }
+    }
+}
+
+/// The removal history of every roll: the (1-based) wave in which it was
+/// removed, or membership of the stable core that is never removed.
+struct LayerMap {
+    layers: HashMap<Coord<2>, usize>,
+    core: HashSet<Coord<2>>,
+    wave_sizes: Vec<usize>,
+    max: Coord<2>,
+}
+
+impl LayerMap {
+    fn new(map: &Map<2>, waves: &[Vec<Coord<2>>]) -> Self {
+        let mut layers = HashMap::new();
+        for (wave, rolls) in waves.iter().enumerate() {
+            for pos in rolls {
+                layers.insert(*pos, wave + 1);
+            }
+        }
+
+        let core = map.paper.iter()
+            .filter(|pos| !layers.contains_key(*pos))
+            .copied()
+            .collect();
+
+        LayerMap {
+            layers,
+            core,
+            wave_sizes: waves.iter().map(Vec::len).collect(),
+            max: map.max,
+        }
+    }
+
+    fn layer_char(layer: usize) -> char {
+        match layer {
+            1..=9 => char::from_digit(layer as u32, 10).unwrap(),
+            10..=35 => char::from_digit(layer as u32, 36).unwrap(),
+            _ => '+',
+        }
+    }
+
+    /// Draws the grid with `.` for empty cells, `@` for the core and the wave
+    /// of every removed roll.
+    fn render(&self, style: LayerStyle) -> String {
+        // Readable backgrounds from the 256-colour palette, cycled per wave.
+        let palette = [196, 208, 226, 118, 51, 33, 129, 201];
+
+        let mut output = String::new();
+        for y in 0..=self.max[1] {
+            for x in 0..=self.max[0] {
+                let pos = [x, y];
+                let cell = if let Some(layer) = self.layers.get(&pos) {
+                    let char = Self::layer_char(*layer);
+                    match style {
+                        LayerStyle::Digits => char.to_string(),
+                        LayerStyle::Colour => {
+                            format!("\x1b[30;48;5;{}m{char}\x1b[0m", palette[(layer - 1) % palette.len()])
+                        }
+                    }
+                } else if self.core.contains(&pos) {
+                    match style {
+                        LayerStyle::Digits => "@".to_string(),
+                        LayerStyle::Colour => "\x1b[1;97m@\x1b[0m".to_string(),
+                    }
+                } else {
+                    ".".to_string()
+                };
+
+                output.push_str(&cell);
+            }
+            output.push('\n');
+        }
+
+        output
+    }
+
+    /// One `x,y,layer` row per roll, where `layer` is the wave number or `core`.
+    fn to_csv(&self) -> String {
+        let mut rolls: Vec<(Coord<2>, Option<usize>)> = self.layers.iter()
+            .map(|(pos, layer)| (*pos, Some(*layer)))
+            .chain(self.core.iter().map(|pos| (*pos, None)))
+            .collect();
+        rolls.sort_by_key(|([x, y], _)| (*y, *x));
+
+        let mut csv = String::from("x,y,layer\n");
+        for ([x, y], layer) in rolls {
+            match layer {
+                Some(layer) => writeln!(csv, "{x},{y},{layer}").unwrap(),
+                None => writeln!(csv, "{x},{y},core").unwrap(),
+            }
+        }
+
+        csv
+    }
+}
+
+/// Settings for rendering the removal process frame by frame.
+struct Animation {
+    cell_size: u32,
+    delay_ms: u32,
+}
+
+impl Animation {
+    const BACKGROUND: Rgba<u8> = Rgba([20, 20, 20, 255]);
+    const REMAINING: Rgba<u8> = Rgba([235, 225, 200, 255]);
+    const ABOUT_TO_GO: Rgba<u8> = Rgba([220, 40, 40, 255]);
+    const REMOVED: Rgba<u8> = Rgba([80, 80, 80, 255]);
+
+    /// One frame before every wave, showing the rolls it is about to remove,
+    /// plus a final frame of the stable core.
+    fn frames(&self, map: &Map<2>, waves: &[Vec<Coord<2>>]) -> Vec<RgbaImage> {
+        let width = (map.max[0] as u32 + 1) * self.cell_size;
+        let height = (map.max[1] as u32 + 1) * self.cell_size;
+
+        let mut removed: HashSet<Coord<2>> = HashSet::new();
+        let mut frames = vec![];
+
+        for wave in 0..=waves.len() {
+            let about_to_go: HashSet<Coord<2>> = waves.get(wave)
+                .map(|rolls| rolls.iter().copied().collect())
+                .unwrap_or_default();
+
+            let mut img = RgbaImage::from_pixel(width, height, Self::BACKGROUND);
+            for pos in &map.paper {
+                let colour = if about_to_go.contains(pos) {
+                    Self::ABOUT_TO_GO
+                } else if removed.contains(pos) {
+                    Self::REMOVED
+                } else {
+                    Self::REMAINING
+                };
+
+                let (x, y) = (pos[0] * self.cell_size as i32, pos[1] * self.cell_size as i32);
+                draw_filled_rect_mut(&mut img, Rect::at(x, y).of_size(self.cell_size, self.cell_size), colour);
+            }
+
+            frames.push(img);
+            removed.extend(about_to_go);
+        }
+
+        frames
+    }
+
+    /// Writes an animated GIF if `path` ends in `.gif`, otherwise a numbered
+    /// PNG sequence (`frame_0000.png`, ...) into the directory `path`.
+    fn save(&self, map: &Map<2>, waves: &[Vec<Coord<2>>], path: &str) {
+        let frames = self.frames(map, waves);
+
+        if path.ends_with(".gif") {
+            let file = std::fs::File::create(path).expect("Expected writable GIF path");
+            let mut encoder = GifEncoder::new(file);
+            encoder.set_repeat(Repeat::Infinite).expect("Failed to set GIF repeat");
+
+            let delay = Delay::from_numer_denom_ms(self.delay_ms, 1);
+            encoder
+                .encode_frames(frames.into_iter().map(|img| Frame::from_parts(img, 0, 0, delay)))
+                .expect("Failed to encode GIF");
+        } else {
+            std::fs::create_dir_all(path).expect("Expected writable frame directory");
+            for (i, img) in frames.iter().enumerate() {
+                img.save(format!("{path}/frame_{i:04}.png")).expect("save failed");
+            }
+        }
+    }
+}
+
+struct Options {
+    input: Option<String>,
+    dimensions: usize,
+    wrap: bool,
+    bits: bool,
+    rules: Rules,
+    verify: bool,
+    layers: Option<LayerStyle>,
+    csv: Option<String>,
+    render: Option<String>,
+    animation: Animation,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        input: None,
+        dimensions: 2,
+        wrap: false,
+        bits: false,
+        rules: Rules::default(),
+        verify: false,
+        layers: None,
+        csv: None,
+        render: None,
+        animation: Animation {
+            cell_size: 4,
+            delay_ms: 100,
+        },
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("Expected a value after '{arg}'"));
+
+        match arg.as_str() {
+            "--input" => options.input = Some(value()),
+            "--dimensions" => options.dimensions = value().parse().expect("Expected a number of dimensions"),
+            "--wrap" => options.wrap = true,
+            "--bits" => options.bits = true,
+            "--neighbourhood" => {
+                options.rules.neighbourhood = value().parse().unwrap_or_else(|e| panic!("{e}"));
+            }
+            "--threshold" => {
+                options.rules.threshold = value().parse().unwrap_or_else(|e| panic!("{e}"));
+            }
+            "--verify" => options.verify = true,
+            "--layers" => options.layers = Some(value().parse().unwrap_or_else(|e| panic!("{e}"))),
+            "--csv" => options.csv = Some(value()),
+            "--render" => options.render = Some(value()),
+            "--cell-size" => {
+                options.animation.cell_size = value().parse().ok().filter(|size| *size > 0).expect("Expected a positive cell size");
+            }
+            "--delay" => options.animation.delay_ms = value().parse().expect("Expected a delay in milliseconds"),
+            _ => panic!("Unknown argument '{arg}'"),
+        }
+    }
+
+    options
+}
+
+/// Prints both parts and returns the removal waves, so the wave history and
+/// rendering can reuse them.
+fn run<const N: usize>(map: &Map<N>, options: &Options) -> Vec<Vec<Coord<N>>> {
+    println!("part1: {}", part1(map, &options.rules));
+    let waves = removal_waves(map, &options.rules);
+    let removed = part2(&waves);
+    println!("part2: {removed}");
+
+    if options.verify {
+        assert_eq!(removed, part2_rounds(map, &options.rules), "Worklist and round-based removal disagree");
+        println!("verify: ok");
+    }
+
+    waves
+}
+
+fn run_bits(lines: &[String], options: &Options) {
+    assert!(
+        options.dimensions == 2 && !options.wrap,
+        "The bit-grid backend only supports flat 2D maps"
+    );
+    assert!(
+        options.layers.is_none() && options.csv.is_none() && options.render.is_none(),
+        "Wave history and rendering need the hash-set backend"
+    );
+
+    let grid = BitGrid::parse(lines);
+    let accessible = grid.part1(&options.rules);
+    let removed = grid.part2(&options.rules);
+
+    println!("part1: {accessible}");
+    println!("part2: {removed}");
+
+    if options.verify {
+        let map = parse::<2>(lines, false);
+        assert_eq!(accessible, part1(&map, &options.rules), "Bit-grid and hash-set part1 disagree");
+        assert_eq!(removed, part2(&removal_waves(&map, &options.rules)), "Bit-grid and hash-set part2 disagree");
+        println!("verify: ok");
+    }
 }
 
 fn main() {
-    let lines = read_input();
-    let map = parse(&lines);
+    let options = parse_args();
+    let lines = read_input(options.input.as_deref());
+
+    if options.bits {
+        return run_bits(&lines, &options);
+    }
+
+    assert!(
+        options.dimensions == 2 || (options.layers.is_none() && options.csv.is_none() && options.render.is_none()),
+        "Wave history and rendering only support 2D maps"
+    );
+
+    // Everything else is generic over the dimension count, but it has to be
+    // known at compile time, so each supported count needs an arm here.
+    let map = match options.dimensions {
+        2 => parse::<2>(&lines, options.wrap),
+        3 => {
+            run(&parse::<3>(&lines, options.wrap), &options);
+            return;
+        }
+        4 => {
+            run(&parse::<4>(&lines, options.wrap), &options);
+            return;
+        }
+        n => panic!("Unsupported number of dimensions: {n}, expected 2, 3 or 4"),
+    };
+
+    let waves = run(&map, &options);
+
+    if options.layers.is_some() || options.csv.is_some() {
+        let layer_map = LayerMap::new(&map, &waves);
+
+        println!("waves: {}", layer_map.wave_sizes.len());
+        println!("per wave: {:?}", layer_map.wave_sizes);
+        println!("core: {}", layer_map.core.len());
 
-    println!("part1: {}", part1(&map));
-    println!("part2: {}", part2(&map));
+        if let Some(style) = options.layers {
+            print!("{}", layer_map.render(style));
+        }
+
+        if let Some(path) = &options.csv {
+            std::fs::write(path, layer_map.to_csv()).expect("Expected writable CSV path");
+        }
+    }
+
+    if let Some(path) = &options.render {
+        options.animation.save(&map, &waves, path);
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(input: &str) -> Vec<String> {
+        input.lines().map(str::to_string).collect()
+    }
+
+    /// A fixed, irregular pattern of rolls with a mix of neighbour counts.
+    fn grid(width: usize, height: usize) -> Vec<String> {
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| if ((x * 31 + y * 17) ^ (x * y)) % 7 < 4 { '@' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn rule_sets() -> Vec<Rules> {
+        [
+            ("moore", "<4"),
+            ("moore", "=3"),
+            ("von-neumann", "<=2"),
+            ("chebyshev:2", ">=5"),
+            ("chebyshev:2", "<12"),
+            ("manhattan:2", "=3"),
+        ]
+        .into_iter()
+        .map(|(neighbourhood, threshold)| Rules {
+            neighbourhood: neighbourhood.parse().unwrap(),
+            threshold: threshold.parse().unwrap(),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn waves_match_rounds() {
+        for (width, height) in [(1, 1), (5, 7), (20, 20), (63, 9), (64, 9), (65, 9)] {
+            let lines = grid(width, height);
+            let flat = parse::<2>(&lines, false);
+            let stacked = parse::<3>(&[lines.clone(), vec![String::new()], lines].concat(), false);
+
+            for rules in rule_sets() {
+                assert_eq!(part2(&removal_waves(&flat, &rules)), part2_rounds(&flat, &rules), "{width}x{height} with {rules:?}");
+                assert_eq!(
+                    part2(&removal_waves(&stacked, &rules)),
+                    part2_rounds(&stacked, &rules),
+                    "{width}x{height}x2 with {rules:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn waves_match_rounds_when_wrapping() {
+        let map = parse::<2>(&grid(9, 11), true);
+        for rules in rule_sets() {
+            assert_eq!(part2(&removal_waves(&map, &rules)), part2_rounds(&map, &rules), "{rules:?}");
+        }
+    }
+
+    #[test]
+    fn neighbourhood_sizes() {
+        let count = |neighbourhood: &str, dimensions| {
+            let neighbourhood: Neighbourhood = neighbourhood.parse().unwrap();
+            match dimensions {
+                2 => neighbourhood.displacements::<2>().len(),
+                3 => neighbourhood.displacements::<3>().len(),
+                _ => unreachable!(),
+            }
+        };
+
+        assert_eq!(count("von-neumann", 2), 4);
+        assert_eq!(count("von-neumann", 3), 6);
+        assert_eq!(count("moore", 2), 8);
+        assert_eq!(count("moore", 3), 26);
+        assert_eq!(count("chebyshev:1", 2), 8);
+        assert_eq!(count("chebyshev:2", 2), 24);
+        assert_eq!(count("manhattan:1", 2), 4);
+        assert_eq!(count("manhattan:2", 2), 12);
+    }
+
+    #[test]
+    fn moore_offsets_are_the_surrounding_cells() {
+        let mut offsets = Neighbourhood::Moore.displacements::<2>();
+        offsets.sort_unstable();
+        assert_eq!(offsets, [[-1, -1], [-1, 0], [-1, 1], [0, -1], [0, 1], [1, -1], [1, 0], [1, 1]]);
+    }
+
+    #[test]
+    fn empty_leading_layer_still_counts() {
+        let map = parse::<3>(&lines("..\n..\n\n@.\n..\n\n@.\n.."), false);
+        assert_eq!(map.paper, HashSet::from([[0, 0, 1], [0, 0, 2]]));
+
+        let rules = Rules { neighbourhood: Neighbourhood::Moore, threshold: "=1".parse().unwrap() };
+        assert_eq!(part1(&map, &rules), 2);
+    }
+
+    #[test]
+    fn bit_grid_matches_hash_set() {
+        for width in [1, 5, 63, 64, 65, 100, 127, 128, 129, 200] {
+            let lines = grid(width, 13);
+            let grid = BitGrid::parse(&lines);
+            let map = parse::<2>(&lines, false);
+
+            for rules in rule_sets() {
+                assert_eq!(grid.part1(&rules), part1(&map, &rules), "width {width} with {rules:?}");
+                assert_eq!(grid.part2(&rules), part2(&removal_waves(&map, &rules)), "width {width} with {rules:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn bit_grid_handles_ragged_rows() {
+        let lines = lines("@@@@\n@@\n@@@@@@@\n@");
+        let grid = BitGrid::parse(&lines);
+        let map = parse::<2>(&lines, false);
+
+        for rules in rule_sets() {
+            assert_eq!(grid.part1(&rules), part1(&map, &rules), "{rules:?}");
+            assert_eq!(grid.part2(&rules), part2(&removal_waves(&map, &rules)), "{rules:?}");
+        }
+    }
+
+    const EXAMPLE: &str = "\
+..@@.@@@@.
+@@@.@.@.@@
+@@@@@.@.@@
+@.@@@@..@.
+@@.@@@@.@@
+.@@@@@@@.@
+.@.@.@.@@@
+@.@@@.@@@@
+.@@@@@@@@.
+@.@.@@@.@.";
+
+    #[test]
+    fn example_wave_history() {
+        let map = parse::<2>(&lines(EXAMPLE), false);
+        let layer_map = LayerMap::new(&map, &removal_waves(&map, &Rules::default()));
+
+        assert_eq!(layer_map.wave_sizes, [13, 12, 7, 5, 2, 1, 1, 1, 1]);
+        assert_eq!(layer_map.core.len(), 28);
+
+        let csv = layer_map.to_csv();
+        assert_eq!(csv.lines().next(), Some("x,y,layer"));
+        assert_eq!(csv.lines().count(), 1 + 71);
+        assert_eq!(csv.lines().filter(|line| line.ends_with(",core")).count(), 28);
+        assert_eq!(csv.lines().filter(|line| line.ends_with(",1")).count(), 13);
+
+        let render = layer_map.render(LayerStyle::Digits);
+        assert_eq!(render.lines().count(), 10);
+        assert_eq!(render.matches('@').count(), 28);
+        assert_eq!(render.matches('1').count(), 13);
+        assert_eq!(render.matches('2').count(), 12);
+        assert_eq!(render.matches('3').count(), 7);
+        assert_eq!(render.lines().next(), Some("..11.1121."));
+    }
+}
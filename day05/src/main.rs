@@ -1,119 +1,894 @@
-use std::{cmp::Ordering, collections::HashSet, future::poll_fn, ops::RangeInclusive};
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{BufRead, BufReader, Write},
+    ops::RangeInclusive,
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    sync::{Arc, RwLock},
+    thread,
+};
 
-fn read_input() -> (String, String) {
-    let input = include_str!("../input");
-    input.split_once("\n\n").map(|(ranges, ids)| (ranges.to_string(), ids.to_string())).expect("Expected ranges and IDs")
+fn read_input() -> &'static str {
+    include_str!("../input")
 }
 
+#[derive(Debug)]
 struct Problem {
-    ranges: Vec<RangeInclusive<u64>>,
+    ranges: Vec<SourceRange>,
     ids: HashSet<u64>,
 }
 
-fn parse((ranges, ids): &(String, String)) -> Problem {
-    let ranges = ranges.lines()
-        .map(|line| {
-            let (start, end) = line.split_once('-').expect("Expected 'start-end'");
-           start.parse().expect("Expected start of range number")..=end.parse().expect("Expected end of range number") 
+/// A range as written in the input, with its 1-based line number.
+#[derive(Clone, Debug)]
+struct SourceRange {
+    line: usize,
+    range: RangeInclusive<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseErrorKind {
+    /// Not any of the accepted range forms.
+    Malformed(String),
+    /// Not a number, or too large for an ID.
+    InvalidNumber(String),
+    /// A range whose end comes before its start, like `5-3`.
+    Reversed { start: u64, end: u64 },
+    /// A half-open range without any IDs, like `5..5`.
+    Empty { start: u64, end: u64 },
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseErrorKind::Malformed(text) => write!(f, "expected a range like 'a-b', 'a..b', 'a-', '-b' or 'a', got '{text}'"),
+            ParseErrorKind::InvalidNumber(text) => write!(f, "'{text}' is not an ID between 0 and {}", u64::MAX),
+            ParseErrorKind::Reversed { start, end } => write!(f, "range {start}-{end} ends before it starts"),
+            ParseErrorKind::Empty { start, end } => write!(f, "half-open range {start}..{end} is empty"),
+        }
+    }
+}
 
-        })
-        .collect();
+#[derive(Debug)]
+struct ParseError {
+    line: usize,
+    kind: ParseErrorKind,
+}
 
-    let ids = ids.lines()
-        .map(|line| {
-            line.parse().expect("Expected id number")
-        })
-        .collect();
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
 
-    Problem { ranges, ids }
+fn parse_id(text: &str) -> Result<u64, ParseErrorKind> {
+    let text = text.trim();
+    text.parse().map_err(|_| ParseErrorKind::InvalidNumber(text.to_string()))
 }
 
-fn part1(problem: &Problem) -> usize {
-    let mut remaining_ids = problem.ids.clone();
-    let mut fresh_ids = vec![];
-    let mut new_fresh_id_index = 0;
+/// Parses one range in any of the accepted forms:
+///
+/// - `a-b` and `a..=b`: from `a` to `b` inclusive,
+/// - `a..b`: from `a` up to but not including `b`,
+/// - `a-` and `a..`: from `a` upwards,
+/// - `-b` and `..=b`: up to `b` inclusive, `..b`: up to `b` exclusive,
+/// - `a`: the single ID `a`.
+///
+/// Whitespace around numbers and separators is ignored.
+fn parse_range(text: &str) -> Result<RangeInclusive<u64>, ParseErrorKind> {
+    let text = text.trim();
+    let bound = |side: &str| {
+        let side = side.trim();
+        if side.is_empty() { Ok(None) } else { parse_id(side).map(Some) }
+    };
 
-    for range in &problem.ranges {
-        for id in &remaining_ids {
-            if range.contains(id) {
-                fresh_ids.push(*id);
+    let (start, end) = if let Some((start, end)) = text.split_once("..=") {
+        (bound(start)?, bound(end)?)
+    } else if let Some((start, end)) = text.split_once("..") {
+        let start = bound(start)?;
+        let end = match bound(end)? {
+            Some(end) => {
+                let start = start.unwrap_or(0);
+                if end <= start {
+                    return Err(if end == start {
+                        ParseErrorKind::Empty { start, end }
+                    } else {
+                        ParseErrorKind::Reversed { start, end }
+                    });
+                }
+                Some(end - 1)
             }
+            None => None,
+        };
+        (start, end)
+    } else if let Some((start, end)) = text.split_once('-') {
+        (bound(start)?, bound(end)?)
+    } else if text.is_empty() {
+        return Err(ParseErrorKind::Malformed(text.to_string()));
+    } else {
+        let id = parse_id(text)?;
+        (Some(id), Some(id))
+    };
+
+    // A separator needs at least one bound, so `-`, `..` and `..=` alone
+    // are not taken to mean every ID.
+    if start.is_none() && end.is_none() {
+        return Err(ParseErrorKind::Malformed(text.to_string()));
+    }
+
+    let (start, end) = (start.unwrap_or(0), end.unwrap_or(u64::MAX));
+    if end < start {
+        return Err(ParseErrorKind::Reversed { start, end });
+    }
+
+    Ok(start..=end)
+}
+
+/// Splits the input into the range section and the ID section at the first
+/// blank line after a range. Everything after a `#` is a comment, and lines
+/// holding only a comment are skipped.
+fn parse(input: &str) -> Result<Problem, ParseError> {
+    let mut ranges = vec![];
+    let mut ids = HashSet::new();
+    let mut in_ids = false;
+
+    for (index, line) in input.lines().enumerate() {
+        let error = |kind| ParseError { line: index + 1, kind };
+
+        if line.trim().is_empty() {
+            in_ids |= !ranges.is_empty();
+            continue;
         }
 
-        for seen_id in &fresh_ids[new_fresh_id_index..] {
-            remaining_ids.remove(seen_id);
+        let text = line.split('#').next().unwrap_or_default().trim();
+        if text.is_empty() {
+            continue;
         }
 
-        new_fresh_id_index = fresh_ids.len();
+        if in_ids {
+            ids.insert(parse_id(text).map_err(error)?);
+        } else {
+            let range = parse_range(text).map_err(error)?;
+            ranges.push(SourceRange { line: index + 1, range });
+        }
     }
 
-    fresh_ids.len()
+    Ok(Problem { ranges, ids })
 }
 
-fn range_contains(first: &RangeInclusive<u64>, second: &RangeInclusive<u64>) -> bool {
-    first.contains(second.start()) && first.contains(second.end())
+/// Integer types a `RangeSet` can hold.
+trait Discrete: Copy + Ord {
+    const MIN: Self;
+    const MAX: Self;
+
+    fn next(self) -> Option<Self>;
+    fn prev(self) -> Option<Self>;
+
+    /// The number of values in `start..=end`, assuming `start <= end`.
+    fn span(start: Self, end: Self) -> u128;
 }
 
-fn part2(problem: &Problem) -> usize {
-    // Remove all ranges that are contained inside another range
-    let mut ranges = problem.ranges.clone();
+impl Discrete for u64 {
+    const MIN: Self = u64::MIN;
+    const MAX: Self = u64::MAX;
+
+    fn next(self) -> Option<Self> {
+        self.checked_add(1)
+    }
 
-    ranges.sort_by(|first, second| {
-        let start_cmp = first.start().cmp(second.start());
+    fn prev(self) -> Option<Self> {
+        self.checked_sub(1)
+    }
 
-        if start_cmp == Ordering::Equal {
-            first.end().cmp(second.end())
-        } else {
-            start_cmp
+    fn span(start: Self, end: Self) -> u128 {
+        u128::from(end - start) + 1
+    }
+}
+
+/// A set of integers stored as sorted, disjoint inclusive ranges. Ranges that
+/// overlap or touch (like `1-3` and `4-6`) are always merged.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct RangeSet<T> {
+    ranges: Vec<RangeInclusive<T>>,
+}
+
+impl<T: Discrete> RangeSet<T> {
+    fn from_ranges(ranges: impl IntoIterator<Item = RangeInclusive<T>>) -> Self {
+        let mut set = RangeSet {
+            ranges: ranges.into_iter().collect(),
+        };
+        set.normalize();
+        set
+    }
+
+    /// Drops empty ranges, sorts the rest and merges overlapping or adjacent ones.
+    fn normalize(&mut self) {
+        self.ranges.retain(|range| range.start() <= range.end());
+        self.ranges.sort_by_key(|range| *range.start());
+
+        let mut merged: Vec<RangeInclusive<T>> = Vec::with_capacity(self.ranges.len());
+        for range in self.ranges.drain(..) {
+            if let Some(last) = merged.last_mut() {
+                let touches = last.end().next().is_none_or(|after_last| *range.start() <= after_last);
+
+                if touches {
+                    if range.end() > last.end() {
+                        *last = *last.start()..=*range.end();
+                    }
+                    continue;
+                }
+            }
+
+            merged.push(range);
         }
-    });
 
-    loop {
-        let mut new_ranges: Vec<RangeInclusive<u64>> = vec![];
+        self.ranges = merged;
+    }
 
-        let mut first_index = 0;
+    fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
 
-        while first_index + 1 < ranges.len() {
-            let first = &ranges[first_index];
-            let second = &ranges[first_index + 1];
+    /// Binary search over the sorted, disjoint ranges.
+    fn contains(&self, value: T) -> bool {
+        let index = self.ranges.partition_point(|range| *range.end() < value);
+        self.ranges.get(index).is_some_and(|range| *range.start() <= value)
+    }
+
+    /// The number of values in the set.
+    fn len(&self) -> u128 {
+        self.ranges
+            .iter()
+            .map(|range| T::span(*range.start(), *range.end()))
+            .sum()
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        Self::from_ranges(self.ranges.iter().chain(&other.ranges).cloned())
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        let mut ranges = vec![];
+        let (mut i, mut j) = (0, 0);
+
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (first, second) = (&self.ranges[i], &other.ranges[j]);
 
-            if range_contains(first, second) {
-                new_ranges.push(first.clone());
-                first_index += 2;
-                continue;
+            let start = *first.start().max(second.start());
+            let end = *first.end().min(second.end());
+            if start <= end {
+                ranges.push(start..=end);
             }
 
-            if first.contains(second.start()) {
-                let merged_range = (*first.start())..=(*second.end());
-                new_ranges.push(merged_range);
-                first_index += 2;
-                continue;
+            if first.end() < second.end() {
+                i += 1;
+            } else {
+                j += 1;
             }
+        }
+
+        RangeSet { ranges }
+    }
+
+    fn difference(&self, other: &Self) -> Self {
+        self.intersection(&other.complement(T::MIN..=T::MAX))
+    }
+
+    /// All values within `bounds` that are not in the set.
+    fn complement(&self, bounds: RangeInclusive<T>) -> Self {
+        let mut ranges = vec![];
+        let mut next_start = Some(*bounds.start());
 
-            new_ranges.push(first.clone());
-            first_index += 1;
+        for range in &self.ranges {
+            let Some(start) = next_start else {
+                break;
+            };
+
+            if let Some(end) = range.start().prev() {
+                let end = end.min(*bounds.end());
+                if start <= end {
+                    ranges.push(start..=end);
+                }
+            }
+
+            next_start = range.end().next().map(|after| after.max(start));
         }
 
-        if first_index + 1 == ranges.len() {
-            new_ranges.push(ranges.last().unwrap().clone());
+        if let Some(start) = next_start {
+            if start <= *bounds.end() {
+                ranges.push(start..=*bounds.end());
+            }
         }
 
-        if new_ranges.len() == ranges.len() {
-            break;
+        RangeSet { ranges }
+    }
+}
+
+/// The problem's IDs, sorted and split by whether any range contains them.
+struct Freshness {
+    fresh: Vec<u64>,
+    spoiled: Vec<u64>,
+}
+
+fn part1(problem: &Problem) -> Freshness {
+    let fresh_ranges = RangeSet::from_ranges(problem.ranges.iter().map(|source| source.range.clone()));
+
+    let mut ids: Vec<u64> = problem.ids.iter().copied().collect();
+    ids.sort_unstable();
+
+    let (fresh, spoiled) = ids.into_iter().partition(|id| fresh_ranges.contains(*id));
+
+    Freshness { fresh, spoiled }
+}
+
+fn part2(problem: &Problem) -> u128 {
+    RangeSet::from_ranges(problem.ranges.iter().map(|source| source.range.clone())).len()
+}
+
+/// A static interval tree over the original input ranges, to find out which
+/// input lines make an ID fresh.
+///
+/// The ranges are sorted by start and read as an implicit balanced binary tree:
+/// the middle of every slice is a node, and `max_end` holds the largest end in
+/// the subtree under it.
+struct RangeIndex {
+    sources: Vec<SourceRange>,
+    max_end: Vec<u64>,
+}
+
+impl RangeIndex {
+    fn new(sources: &[SourceRange]) -> Self {
+        let mut sources = sources.to_vec();
+        sources.sort_by_key(|source| (*source.range.start(), source.line));
+
+        let mut index = RangeIndex {
+            max_end: vec![0; sources.len()],
+            sources,
+        };
+        index.build(0, index.sources.len());
+
+        index
+    }
+
+    fn build(&mut self, lo: usize, hi: usize) -> Option<u64> {
+        if lo >= hi {
+            return None;
         }
 
-        ranges = new_ranges;
+        let mid = lo + (hi - lo) / 2;
+        let max_end = [self.build(lo, mid), self.build(mid + 1, hi)]
+            .into_iter()
+            .flatten()
+            .fold(*self.sources[mid].range.end(), u64::max);
+        self.max_end[mid] = max_end;
+
+        Some(max_end)
+    }
+
+    /// Every input range containing `id`, ordered by line number.
+    fn containing(&self, id: u64) -> Vec<&SourceRange> {
+        let mut found = vec![];
+        self.collect_containing(id, 0, self.sources.len(), &mut found);
+        found.sort_by_key(|source| source.line);
+
+        found
     }
 
-    ranges.iter()
-        .map(|range| range.clone().count())
-        .sum()
+    fn collect_containing<'a>(&'a self, id: u64, lo: usize, hi: usize, found: &mut Vec<&'a SourceRange>) {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = lo + (hi - lo) / 2;
+        if self.max_end[mid] < id {
+            return;
+        }
+
+        self.collect_containing(id, lo, mid, found);
+
+        // Everything right of `mid` starts at or after it.
+        if *self.sources[mid].range.start() <= id {
+            if self.sources[mid].range.contains(&id) {
+                found.push(&self.sources[mid]);
+            }
+
+            self.collect_containing(id, mid + 1, hi, found);
+        }
+    }
+
+    /// Splits the covered IDs into maximal runs with the same number of
+    /// covering input ranges, as `(run, depth)` pairs.
+    fn depth_profile(&self) -> Vec<(RangeInclusive<u64>, usize)> {
+        // Where the depth changes, and by how much.
+        let mut events: Vec<(u64, isize)> = vec![];
+        for source in &self.sources {
+            events.push((*source.range.start(), 1));
+            if let Some(after) = source.range.end().checked_add(1) {
+                events.push((after, -1));
+            }
+        }
+        events.sort_unstable();
+
+        let mut profile = vec![];
+        let mut depth = 0;
+        let mut i = 0;
+
+        while i < events.len() {
+            let position = events[i].0;
+            while i < events.len() && events[i].0 == position {
+                depth += events[i].1;
+                i += 1;
+            }
+
+            if depth > 0 {
+                let end = events.get(i).map_or(u64::MAX, |(next, _)| next - 1);
+                profile.push((position..=end, depth as usize));
+            }
+        }
+
+        profile
+    }
+
+    /// The largest number of input ranges covering a single ID, and every ID
+    /// covered that many times.
+    fn deepest(&self) -> (usize, RangeSet<u64>) {
+        let profile = self.depth_profile();
+        let max_depth = profile.iter().map(|(_, depth)| *depth).max().unwrap_or(0);
+
+        let deepest = RangeSet::from_ranges(
+            profile.into_iter()
+                .filter(|(_, depth)| *depth == max_depth)
+                .map(|(run, _)| run),
+        );
+
+        (max_depth, deepest)
+    }
+
+    /// Input ranges whose IDs are all covered by other ranges too. Each could be
+    /// removed on its own without changing the fresh IDs, though not
+    /// necessarily all of them together (e.g. two identical lines).
+    fn redundant(&self) -> Vec<&SourceRange> {
+        let covered_twice = RangeSet::from_ranges(
+            self.depth_profile()
+                .into_iter()
+                .filter(|(_, depth)| *depth >= 2)
+                .map(|(run, _)| run),
+        );
+
+        let mut redundant: Vec<&SourceRange> = self.sources.iter()
+            .filter(|source| {
+                RangeSet::from_ranges([source.range.clone()])
+                    .difference(&covered_twice)
+                    .is_empty()
+            })
+            .collect();
+        redundant.sort_by_key(|source| source.line);
+
+        redundant
+    }
+}
+
+/// Answers one query per line until `reader` runs out:
+///
+/// - `<id>` replies `<id> fresh` or `<id> spoiled`,
+/// - `add <range>` marks the range fresh,
+/// - `remove <range>` marks the range spoiled,
+///
+/// where `<range>` takes any form `parse_range` accepts.
+///
+/// Changes go straight into the shared `fresh` set, so every other open stream
+/// sees them on its next query.
+fn serve(reader: impl BufRead, mut writer: impl Write, fresh: &RwLock<RangeSet<u64>>) -> std::io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        let query = line.trim();
+
+        let reply = if query.is_empty() {
+            continue;
+        } else if let Some(range) = query.strip_prefix("add ") {
+            parse_range(range).map_err(|e| e.to_string()).map(|range| {
+                let mut fresh = fresh.write().unwrap();
+                *fresh = fresh.union(&RangeSet::from_ranges([range]));
+                "ok".to_string()
+            })
+        } else if let Some(range) = query.strip_prefix("remove ") {
+            parse_range(range).map_err(|e| e.to_string()).map(|range| {
+                let mut fresh = fresh.write().unwrap();
+                *fresh = fresh.difference(&RangeSet::from_ranges([range]));
+                "ok".to_string()
+            })
+        } else {
+            query.parse::<u64>()
+                .map_err(|_| format!("Expected id number, 'add <range>' or 'remove <range>', got '{query}'"))
+                .map(|id| {
+                    let state = if fresh.read().unwrap().contains(id) { "fresh" } else { "spoiled" };
+                    format!("{id} {state}")
+                })
+        };
+
+        match reply {
+            Ok(reply) => writeln!(writer, "{reply}")?,
+            Err(e) => writeln!(writer, "error: {e}")?,
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Binds `path`, first removing a socket left behind by an earlier run. A
+/// socket something is still listening on, or any other kind of file, is
+/// left alone.
+fn bind_socket(path: &str) -> UnixListener {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        assert!(metadata.file_type().is_socket(), "'{path}' exists and is not a socket");
+        assert!(UnixStream::connect(path).is_err(), "'{path}' is already being served");
+        std::fs::remove_file(path).unwrap_or_else(|e| panic!("Cannot remove stale socket '{path}': {e}"));
+    }
+
+    UnixListener::bind(path).unwrap_or_else(|e| panic!("Cannot bind socket '{path}': {e}"))
+}
+
+/// Serves every connection to the socket at `path` on its own thread, all
+/// sharing one fresh set.
+fn serve_socket(path: &str, fresh: RangeSet<u64>) {
+    let listener = bind_socket(path);
+    let fresh = Arc::new(RwLock::new(fresh));
+
+    for stream in listener.incoming() {
+        let stream = stream.expect("Expected connection");
+        let fresh = Arc::clone(&fresh);
+
+        thread::spawn(move || {
+            let reader = BufReader::new(stream.try_clone().expect("Expected socket clone"));
+            if let Err(e) = serve(reader, stream, &fresh) {
+                eprintln!("connection closed: {e}");
+            }
+        });
+    }
+}
+
+fn print_sources<'a>(sources: impl IntoIterator<Item = &'a SourceRange>) {
+    for source in sources {
+        println!("  line {}: {}-{}", source.line, source.range.start(), source.range.end());
+    }
+}
+
+struct Options {
+    list: bool,
+    which: Vec<u64>,
+    deepest: bool,
+    redundant: bool,
+    stream: bool,
+    socket: Option<String>,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        list: false,
+        which: vec![],
+        deepest: false,
+        redundant: false,
+        stream: false,
+        socket: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("Expected a value after '{arg}'"));
+
+        match arg.as_str() {
+            "--list" => options.list = true,
+            "--which" => options.which.push(value().parse().expect("Expected id number")),
+            "--deepest" => options.deepest = true,
+            "--redundant" => options.redundant = true,
+            "--stream" => options.stream = true,
+            "--socket" => options.socket = Some(value()),
+            _ => panic!("Unknown argument '{arg}'"),
+        }
+    }
+
+    options
 }
 
 fn main() {
+    let options = parse_args();
     let input = read_input();
-    let parsed = parse(&input);
+    let parsed = parse(input).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+
+    if options.stream || options.socket.is_some() {
+        let fresh = RangeSet::from_ranges(parsed.ranges.iter().map(|source| source.range.clone()));
 
-    println!("part1: {}", part1(&parsed));
+        if let Some(path) = &options.socket {
+            serve_socket(path, fresh);
+        } else {
+            let stdin = std::io::stdin();
+            serve(stdin.lock(), std::io::stdout(), &RwLock::new(fresh)).expect("Expected readable stdin");
+        }
+
+        return;
+    }
+
+    let freshness = part1(&parsed);
+    println!("part1: {}", freshness.fresh.len());
     println!("part2: {}", part2(&parsed));
+
+    if options.list {
+        println!("fresh: {:?}", freshness.fresh);
+        println!("spoiled: {:?}", freshness.spoiled);
+    }
+
+    let index = RangeIndex::new(&parsed.ranges);
+
+    for id in &options.which {
+        let sources = index.containing(*id);
+        println!("{id}: {} range(s)", sources.len());
+        print_sources(sources);
+    }
+
+    if options.deepest {
+        let (depth, deepest) = index.deepest();
+        println!("deepest: {} ID(s) covered by {depth} range(s)", deepest.len());
+        for run in &deepest.ranges {
+            println!("  {}-{}", run.start(), run.end());
+        }
+    }
+
+    if options.redundant {
+        let redundant = index.redundant();
+        println!("redundant: {} range(s)", redundant.len());
+        print_sources(redundant);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn set(ranges: &[RangeInclusive<u64>]) -> RangeSet<u64> {
+        RangeSet::from_ranges(ranges.iter().cloned())
+    }
+
+    fn values(ranges: &[RangeInclusive<u64>]) -> BTreeSet<u64> {
+        ranges.iter().cloned().flatten().collect()
+    }
+
+    #[test]
+    fn normalize_merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(set(&[1..=3, 4..=6]).ranges, [1..=6]);
+        assert_eq!(set(&[4..=6, 1..=3]).ranges, [1..=6]);
+        assert_eq!(set(&[1..=3, 5..=6]).ranges, [1..=3, 5..=6]);
+        assert_eq!(set(&[1..=10, 2..=3, 9..=12]).ranges, [1..=12]);
+        assert_eq!(set(&[5..=5, 5..=5]).ranges, [5..=5]);
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = set(&[7..=3]);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn ranges_reaching_the_maximum() {
+        let to_max = set(&[10..=u64::MAX, 5..=9, u64::MAX..=u64::MAX]);
+        assert_eq!(to_max.ranges, [5..=u64::MAX]);
+        assert_eq!(to_max.len(), u128::from(u64::MAX) - 4);
+        assert!(to_max.contains(u64::MAX));
+        assert!(!to_max.contains(4));
+
+        let everything = set(&[0..=u64::MAX]);
+        assert_eq!(everything.len(), 1 << 64);
+        assert!(everything.complement(0..=u64::MAX).is_empty());
+        assert_eq!(to_max.complement(0..=u64::MAX).ranges, [0..=4]);
+        assert_eq!(set(&[0..=3]).complement(0..=u64::MAX).ranges, [4..=u64::MAX]);
+    }
+
+    #[test]
+    fn complement_within_bounds_that_cut_through_ranges() {
+        let ranges = set(&[3..=7, 10..=12, 20..=25]);
+        assert_eq!(ranges.complement(5..=11).ranges, [8..=9]);
+        assert_eq!(ranges.complement(0..=30).ranges, [0..=2, 8..=9, 13..=19, 26..=30]);
+        assert_eq!(ranges.complement(4..=6).ranges, []);
+        assert_eq!(ranges.complement(13..=19).ranges, [13..=19]);
+        assert_eq!(ranges.complement(22..=40).ranges, [26..=40]);
+    }
+
+    #[test]
+    fn intersection_of_interleaved_ranges() {
+        let a = set(&[0..=5, 10..=15, 20..=u64::MAX]);
+        let b = set(&[3..=12, 14..=21]);
+        assert_eq!(a.intersection(&b).ranges, [3..=5, 10..=12, 14..=15, 20..=21]);
+        assert_eq!(a.intersection(&RangeSet::default()).ranges, []);
+        assert_eq!(a.intersection(&a), a);
+    }
+
+    /// Every operation agrees with plain sets of the same small values.
+    #[test]
+    fn operations_match_brute_force() {
+        const LIMIT: u64 = 64;
+        let mut rng = StdRng::seed_from_u64(32);
+        let random_ranges = |rng: &mut StdRng| -> Vec<RangeInclusive<u64>> {
+            (0..rng.gen_range(0, 6))
+                .map(|_| {
+                    let start = rng.gen_range(0, LIMIT);
+                    start..=rng.gen_range(start, LIMIT).min(start + 12)
+                })
+                .collect()
+        };
+
+        for _ in 0..500 {
+            let (a, b) = (random_ranges(&mut rng), random_ranges(&mut rng));
+            let (set_a, set_b) = (set(&a), set(&b));
+            let (values_a, values_b) = (values(&a), values(&b));
+
+            assert_eq!(values(&set_a.ranges), values_a);
+            assert_eq!(set_a.len(), values_a.len() as u128);
+            assert!(set_a.ranges.windows(2).all(|pair| pair[0].end() + 1 < *pair[1].start()));
+            assert!((0..LIMIT).all(|value| set_a.contains(value) == values_a.contains(&value)));
+
+            assert_eq!(values(&set_a.union(&set_b).ranges), &values_a | &values_b);
+            assert_eq!(values(&set_a.intersection(&set_b).ranges), &values_a & &values_b);
+            assert_eq!(values(&set_a.difference(&set_b).ranges), &values_a - &values_b);
+
+            let start = rng.gen_range(0, LIMIT);
+            let bounds = start..=rng.gen_range(start, LIMIT);
+            let outside: BTreeSet<u64> = bounds.clone().filter(|value| !values_a.contains(value)).collect();
+            assert_eq!(values(&set_a.complement(bounds).ranges), outside);
+        }
+    }
+
+    fn sources(ranges: &[RangeInclusive<u64>]) -> Vec<SourceRange> {
+        ranges.iter()
+            .enumerate()
+            .map(|(i, range)| SourceRange { line: i + 1, range: range.clone() })
+            .collect()
+    }
+
+    fn lines<'a>(sources: impl IntoIterator<Item = &'a SourceRange>) -> Vec<usize> {
+        sources.into_iter().map(|source| source.line).collect()
+    }
+
+    #[test]
+    fn containing_matches_a_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(34);
+
+        for _ in 0..200 {
+            let ranges: Vec<RangeInclusive<u64>> = (0..rng.gen_range(0, 40))
+                .map(|_| {
+                    let start = rng.gen_range(0, 100);
+                    start..=rng.gen_range(start, 110)
+                })
+                .collect();
+            let sources = sources(&ranges);
+            let index = RangeIndex::new(&sources);
+
+            for id in 0..=115 {
+                let expected = lines(sources.iter().filter(|source| source.range.contains(&id)));
+                assert_eq!(lines(index.containing(id)), expected, "id {id} in {ranges:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn containing_reaches_the_maximum() {
+        let index = RangeIndex::new(&sources(&[5..=u64::MAX, 0..=10, u64::MAX..=u64::MAX]));
+        assert_eq!(lines(index.containing(u64::MAX)), [1, 3]);
+        assert_eq!(lines(index.containing(7)), [1, 2]);
+        assert_eq!(lines(index.containing(11)), [1]);
+    }
+
+    #[test]
+    fn identical_lines_are_each_redundant() {
+        let index = RangeIndex::new(&sources(&[3..=5, 10..=20, 3..=5]));
+        assert_eq!(lines(index.redundant()), [1, 3]);
+        assert_eq!(index.deepest(), (2, set(&[3..=5])));
+    }
+
+    #[test]
+    fn ranges_covered_by_neighbours_are_redundant() {
+        let index = RangeIndex::new(&sources(&[1..=5, 3..=8, 6..=10, 12..=12]));
+        assert_eq!(lines(index.redundant()), [2]);
+        assert_eq!(index.deepest(), (2, set(&[3..=8])));
+    }
+
+    #[test]
+    fn serve_answers_queries_and_applies_changes() {
+        let fresh = RwLock::new(set(&[3..=5, 10..=20]));
+        let queries = "4\n\n  6  \nadd 6..8\n6\n8\nremove 12-\n15\n10\nremove 9-3\nfresh?\n";
+        let mut replies = vec![];
+
+        serve(std::io::Cursor::new(queries), &mut replies, &fresh).unwrap();
+
+        assert_eq!(
+            String::from_utf8(replies).unwrap().lines().collect::<Vec<_>>(),
+            [
+                "4 fresh",
+                "6 spoiled",
+                "ok",
+                "6 fresh",
+                "8 spoiled",
+                "ok",
+                "15 spoiled",
+                "10 fresh",
+                "error: range 9-3 ends before it starts",
+                "error: Expected id number, 'add <range>' or 'remove <range>', got 'fresh?'",
+            ]
+        );
+        assert_eq!(*fresh.read().unwrap(), set(&[3..=7, 10..=11]));
+    }
+
+    #[test]
+    fn parse_range_accepts_every_form() {
+        let cases = [
+            ("3-7", 3..=7),
+            ("3..7", 3..=6),
+            ("3..=7", 3..=7),
+            ("3-", 3..=u64::MAX),
+            ("3..", 3..=u64::MAX),
+            ("-7", 0..=7),
+            ("..7", 0..=6),
+            ("..=7", 0..=7),
+            ("7", 7..=7),
+            (" 3 - 7 ", 3..=7),
+            ("5-5", 5..=5),
+            ("0..=18446744073709551615", 0..=u64::MAX),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(parse_range(text), Ok(expected), "'{text}'");
+        }
+    }
+
+    #[test]
+    fn parse_range_rejects_bad_ranges() {
+        let malformed = |text: &str| ParseErrorKind::Malformed(text.to_string());
+        let cases = [
+            ("5-3", ParseErrorKind::Reversed { start: 5, end: 3 }),
+            ("5..=3", ParseErrorKind::Reversed { start: 5, end: 3 }),
+            ("5..3", ParseErrorKind::Reversed { start: 5, end: 3 }),
+            ("5..5", ParseErrorKind::Empty { start: 5, end: 5 }),
+            ("..0", ParseErrorKind::Empty { start: 0, end: 0 }),
+            ("-", malformed("-")),
+            ("..", malformed("..")),
+            ("..=", malformed("..=")),
+            ("", malformed("")),
+            ("3-x", ParseErrorKind::InvalidNumber("x".to_string())),
+            ("18446744073709551616", ParseErrorKind::InvalidNumber("18446744073709551616".to_string())),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(parse_range(text), Err(expected), "'{text}'");
+        }
+    }
+
+    #[test]
+    fn parse_skips_comments_and_reports_lines() {
+        let input = "\
+# fresh ranges
+3-5
+# more after a comment line
+10..=14  # inclusive
+
+1
+5 # spoiled?
+
+14
+";
+        let problem = parse(input).unwrap();
+        let ranges: Vec<_> = problem.ranges.iter().map(|source| (source.line, source.range.clone())).collect();
+        assert_eq!(ranges, [(2, 3..=5), (4, 10..=14)]);
+        assert_eq!(problem.ids, HashSet::from([1, 5, 14]));
+
+        let error = parse("# header\n1-2\n\n4..4\n").unwrap_err();
+        assert_eq!((error.line, error.kind), (4, ParseErrorKind::InvalidNumber("4..4".to_string())));
+
+        let error = parse("1-2\n# note\n5..5 # empty\n").unwrap_err();
+        assert_eq!((error.line, error.kind), (3, ParseErrorKind::Empty { start: 5, end: 5 }));
+    }
 }
@@ -1,27 +1,211 @@
+use std::fmt;
+
+use num_bigint::{BigInt, BigUint, Sign};
+
 fn read_input() -> String {
     let input = include_str!("../input");
     input.to_string()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Operation {
     Add,
     Multiply,
+    Subtract,
+    Divide,
+    Min,
+    Max,
+    Power,
+    Concat,
 }
 
+impl Operation {
+    const ALL: [Operation; 8] = [
+        Operation::Add,
+        Operation::Multiply,
+        Operation::Subtract,
+        Operation::Divide,
+        Operation::Min,
+        Operation::Max,
+        Operation::Power,
+        Operation::Concat,
+    ];
+
+    fn symbol(self) -> char {
+        match self {
+            Operation::Add => '+',
+            Operation::Multiply => '*',
+            Operation::Subtract => '-',
+            Operation::Divide => '/',
+            Operation::Min => '<',
+            Operation::Max => '>',
+            Operation::Power => '^',
+            Operation::Concat => '|',
+        }
+    }
+
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        let mut chars = symbol.chars();
+        let (Some(char), None) = (chars.next(), chars.next()) else {
+            return None;
+        };
+
+        Operation::ALL.into_iter().find(|operation| operation.symbol() == char)
+    }
+
+    /// The result of folding no numbers at all, if the operation has one.
+    fn identity(self) -> Option<u64> {
+        match self {
+            Operation::Add => Some(0),
+            Operation::Multiply => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Combines the running result with the next number. Fails with
+    /// `Overflow` whenever the result leaves `u64`, including subtraction
+    /// below zero.
+    fn apply(self, lhs: u64, rhs: u64) -> Result<u64, SolveError> {
+        let result = match self {
+            Operation::Add => lhs.checked_add(rhs),
+            Operation::Multiply => lhs.checked_mul(rhs),
+            Operation::Subtract => lhs.checked_sub(rhs),
+            Operation::Divide => return lhs.checked_div(rhs).ok_or(SolveError::DivisionByZero),
+            Operation::Min => Some(lhs.min(rhs)),
+            Operation::Max => Some(lhs.max(rhs)),
+            Operation::Power => {
+                let exponent = u32::try_from(rhs).map_err(|_| SolveError::ExponentTooLarge)?;
+                lhs.checked_pow(exponent)
+            }
+            Operation::Concat => {
+                // Shifted in `u128`, since `10^20` alone is already too big for
+                // `u64` even when `lhs` is 0.
+                let digits = rhs.checked_ilog10().unwrap_or(0) + 1;
+                u128::from(lhs).checked_mul(10u128.pow(digits))
+                    .and_then(|shifted| shifted.checked_add(u128::from(rhs)))
+                    .and_then(|result| u64::try_from(result).ok())
+            }
+        };
+
+        result.ok_or(SolveError::Overflow)
+    }
+
+    /// Like `apply`, but exact: the running result may grow far beyond `u64`
+    /// (powers only up to `MAX_EXACT_BITS`) or go negative. Division truncates
+    /// towards zero, and concatenating onto a negative result appends digits to
+    /// its magnitude (`-5 | 3` is `-53`).
+    fn apply_exact(self, lhs: BigInt, rhs: u64) -> Result<BigInt, SolveError> {
+        Ok(match self {
+            Operation::Add => lhs + rhs,
+            Operation::Multiply => lhs * rhs,
+            Operation::Subtract => lhs - rhs,
+            Operation::Divide => {
+                if rhs == 0 {
+                    return Err(SolveError::DivisionByZero);
+                }
+                lhs / rhs
+            }
+            Operation::Min => lhs.min(BigInt::from(rhs)),
+            Operation::Max => lhs.max(BigInt::from(rhs)),
+            Operation::Power => {
+                // Powers are the one way a few short numbers can outgrow
+                // memory, so refuse them before computing anything.
+                if lhs.magnitude() > &BigUint::from(1u8) && lhs.bits().saturating_mul(rhs) > MAX_EXACT_BITS {
+                    return Err(SolveError::TooLarge);
+                }
+                lhs.pow(u32::try_from(rhs).map_err(|_| SolveError::ExponentTooLarge)?)
+            }
+            Operation::Concat => {
+                let shifted = lhs * BigInt::from(10u8).pow(rhs.to_string().len() as u32);
+                if shifted.sign() == Sign::Minus {
+                    shifted - rhs
+                } else {
+                    shifted + rhs
+                }
+            }
+        })
+    }
+}
+
+/// The largest exact power worth computing, about 300,000 decimal digits.
+const MAX_EXACT_BITS: u64 = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SolveError {
+    /// The result does not fit in a `u64`.
+    Overflow,
+    DivisionByZero,
+    /// Exponents are limited to `u32::MAX`, even in exact mode.
+    ExponentTooLarge,
+    /// An exact power would take more than `MAX_EXACT_BITS` bits.
+    TooLarge,
+    /// The problem has no numbers, and its operation has no identity.
+    NoNumbers(Operation),
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::Overflow => write!(f, "result does not fit in u64"),
+            SolveError::DivisionByZero => write!(f, "division by zero"),
+            SolveError::ExponentTooLarge => write!(f, "exponent larger than {}", u32::MAX),
+            SolveError::TooLarge => write!(f, "exact result larger than {MAX_EXACT_BITS} bits"),
+            SolveError::NoNumbers(operation) => write!(f, "{operation:?} needs at least one number"),
+        }
+    }
+}
+
+/// A `SolveError` with the index of the problem that caused it, or `None`
+/// when only the grand total overflows.
 #[derive(Debug)]
+struct WorksheetError {
+    problem: Option<usize>,
+    error: SolveError,
+}
+
+impl fmt::Display for WorksheetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.problem {
+            Some(problem) => write!(f, "problem {problem}: {}", self.error),
+            None => write!(f, "total: {}", self.error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 struct Problem {
     numbers: Vec<u64>,
     operation: Operation,
 }
 
 impl Problem {
-    fn solve(&self) -> u64 {
-        match self.operation {
-            Operation::Add => self.numbers.iter().sum::<u64>(),
-            Operation::Multiply => self.numbers.iter().product::<u64>(),
-        }
+    /// Folds the numbers from left to right in the order the parser read them,
+    /// so `-` over `10 2 3` is `(10 - 2) - 3` and `^` over `2 3 2` is
+    /// `(2 ^ 3) ^ 2`. Every step is checked, so nothing wraps silently.
+    fn solve(&self) -> Result<u64, SolveError> {
+        let Some((first, rest)) = self.numbers.split_first() else {
+            return self.operation.identity().ok_or(SolveError::NoNumbers(self.operation));
+        };
+
+        rest.iter()
+            .try_fold(*first, |result, number| self.operation.apply(result, *number))
     }
+
+    /// Like `solve`, folding in the same order with arbitrary precision.
+    fn solve_exact(&self) -> Result<BigInt, SolveError> {
+        let Some((first, rest)) = self.numbers.split_first() else {
+            return self.operation.identity()
+                .map(BigInt::from)
+                .ok_or(SolveError::NoNumbers(self.operation));
+        };
+
+        rest.iter()
+            .try_fold(BigInt::from(*first), |result, number| self.operation.apply_exact(result, *number))
+    }
+}
+
+fn parse_operation(symbol: &str) -> Operation {
+    Operation::from_symbol(symbol).unwrap_or_else(|| panic!("Unknown operation '{symbol}'"))
 }
 
 fn parse1(input: &str) -> Vec<Problem> {
@@ -36,11 +220,7 @@ fn parse1(input: &str) -> Vec<Problem> {
 
     for operation in last.split_whitespace() {
         let problem = Problem {
-            operation: match operation {
-                "*" => Operation::Multiply,
-                "+" => Operation::Add,
-                _ => unreachable!(),
-            },
+            operation: parse_operation(operation),
             numbers: vec![],
         };
 
@@ -60,74 +240,628 @@ fn parse1(input: &str) -> Vec<Problem> {
     problems
 }
 
-fn solve(problems: &[Problem]) -> u64 {
-    problems.iter().map(Problem::solve).sum()
+fn solve(problems: &[Problem]) -> Result<u64, WorksheetError> {
+    problems.iter()
+        .enumerate()
+        .try_fold(0u64, |total, (i, problem)| {
+            let result = problem.solve().map_err(|error| WorksheetError { problem: Some(i), error })?;
+            total.checked_add(result).ok_or(WorksheetError { problem: None, error: SolveError::Overflow })
+        })
 }
 
-fn parse2(input: &str) -> Vec<Problem> {
-    let mut problems = vec![];
+fn solve_exact(problems: &[Problem]) -> Result<BigInt, WorksheetError> {
+    problems.iter()
+        .enumerate()
+        .try_fold(BigInt::from(0u8), |total, (i, problem)| {
+            let result = problem.solve_exact().map_err(|error| WorksheetError { problem: Some(i), error })?;
+            Ok(total + result)
+        })
+}
 
-    let lines = input.lines();
-    let last = lines
-        .clone()
-        .last()
-        .expect("Expected last line to contains operations");
-    let max_len = last.len();
+/// The columns `start..end` of the source text that hold one problem.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ColumnSpan {
+    start: usize,
+    end: usize,
+}
 
-    for operation in last.split_whitespace() {
-        let problem = Problem {
-            operation: match operation {
-                "*" => Operation::Multiply,
-                "+" => Operation::Add,
-                _ => unreachable!(),
-            },
-            numbers: vec![],
-        };
+impl fmt::Display for ColumnSpan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "columns {}-{}", self.start + 1, self.end)
+    }
+}
 
-        problems.push(problem);
+/// A worksheet split into problems. Every problem is a run of columns
+/// separated from the next by a column that is blank in every number row.
+struct Layout {
+    /// The number rows, padded with spaces to the same width.
+    rows: Vec<Vec<char>>,
+    spans: Vec<ColumnSpan>,
+    operations: Vec<Operation>,
+}
+
+/// Lines and columns are 0-based, and shown 1-based.
+#[derive(Debug, PartialEq)]
+enum LayoutError {
+    MissingOperationLine,
+    UnknownOperation { column: usize, symbol: char },
+    /// An operation in a column that belongs to no problem.
+    MisalignedOperation { column: usize },
+    MissingOperation { span: ColumnSpan },
+    ExtraOperation { column: usize, span: ColumnSpan },
+    InvalidNumber { line: usize, column: usize, text: String },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::MissingOperationLine => write!(f, "expected a line of operations"),
+            LayoutError::UnknownOperation { column, symbol } => {
+                write!(f, "unknown operation '{symbol}' at column {}", column + 1)
+            }
+            LayoutError::MisalignedOperation { column } => {
+                write!(f, "operation at column {} is not under any problem", column + 1)
+            }
+            LayoutError::MissingOperation { span } => write!(f, "no operation under the problem in {span}"),
+            LayoutError::ExtraOperation { column, span } => {
+                write!(f, "second operation at column {} under the problem in {span}", column + 1)
+            }
+            LayoutError::InvalidNumber { line, column, text } => {
+                write!(f, "'{text}' at line {}, column {} is not a number", line + 1, column + 1)
+            }
+        }
+    }
+}
+
+fn parse_layout(input: &str) -> Result<Layout, LayoutError> {
+    let mut lines: Vec<&str> = input.lines().collect();
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
     }
 
-    let operation_lines = lines.clone().count() - 1;
-    let char_matrix: Vec<Vec<char>> = lines
-        .take(operation_lines)
-        .map(|line| line.chars().collect())
+    let operation_line: Vec<char> = lines.pop().ok_or(LayoutError::MissingOperationLine)?.chars().collect();
+
+    let width = lines.iter()
+        .map(|line| line.chars().count())
+        .chain([operation_line.len()])
+        .max()
+        .unwrap_or(0);
+    let rows: Vec<Vec<char>> = lines.iter()
+        .map(|line| {
+            let mut row: Vec<char> = line.chars().collect();
+            row.resize(width, ' ');
+            row
+        })
         .collect();
 
-    let mut result = Vec::new();
-    for col in 0..max_len {
-        let mut row = String::new();
-        for r in &char_matrix {
-            row.push(r[col]);
+    let mut spans = vec![];
+    let mut start = None;
+    for column in 0..=width {
+        let blank = column == width || rows.iter().all(|row| row[column] == ' ');
+        match (start, blank) {
+            (None, false) => start = Some(column),
+            (Some(span_start), true) => {
+                spans.push(ColumnSpan { start: span_start, end: column });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let mut operations: Vec<Option<Operation>> = vec![None; spans.len()];
+    for (column, symbol) in operation_line.iter().enumerate() {
+        if *symbol == ' ' {
+            continue;
+        }
+
+        let operation = Operation::from_symbol(&symbol.to_string())
+            .ok_or(LayoutError::UnknownOperation { column, symbol: *symbol })?;
+        let index = spans.iter()
+            .position(|span| (span.start..span.end).contains(&column))
+            .ok_or(LayoutError::MisalignedOperation { column })?;
+
+        if operations[index].replace(operation).is_some() {
+            return Err(LayoutError::ExtraOperation { column, span: spans[index] });
         }
-        result.push(row.trim().to_string());
     }
 
-    let transposed_string = result.join("\n");
-    println!("'{transposed_string}'");
+    let operations = operations.into_iter()
+        .zip(&spans)
+        .map(|(operation, span)| operation.ok_or(LayoutError::MissingOperation { span: *span }))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Layout { rows, spans, operations })
+}
+
+impl Layout {
+    /// The problem's numbers read left to right on each row, one number per
+    /// row from top to bottom. Rows that are blank within the span are skipped.
+    fn row_numbers(&self, span: ColumnSpan) -> Result<Vec<u64>, LayoutError> {
+        self.rows.iter()
+            .enumerate()
+            .filter_map(|(line, row)| {
+                let text: String = row[span.start..span.end].iter().collect();
+                let trimmed = text.trim();
+                if trimmed.is_empty() {
+                    return None;
+                }
+
+                let column = span.start + text.chars().position(|c| c != ' ').unwrap_or(0);
+                Some(trimmed.parse().map_err(|_| LayoutError::InvalidNumber {
+                    line,
+                    column,
+                    text: trimmed.to_string(),
+                }))
+            })
+            .collect()
+    }
+
+    /// The problem's numbers read top to bottom in each column, one number per
+    /// column from left to right.
+    fn column_numbers(&self, span: ColumnSpan) -> Result<Vec<u64>, LayoutError> {
+        (span.start..span.end)
+            .map(|column| {
+                let text: String = self.rows.iter().map(|row| row[column]).collect();
+                let line = text.chars().position(|c| c != ' ').unwrap_or(0);
+
+                text.trim().parse().map_err(|_| LayoutError::InvalidNumber {
+                    line,
+                    column,
+                    text: text.trim().to_string(),
+                })
+            })
+            .collect()
+    }
+}
 
-    for (i, problem_numbers) in transposed_string.split("\n\n").enumerate() {
-        println!("{i}: '{problem_numbers}'");
+fn parse2(input: &str) -> Result<Vec<Problem>, LayoutError> {
+    let layout = parse_layout(input)?;
 
-        let numbers = problem_numbers.lines().map(|number_string| {
-            println!("number: {number_string}");
+    layout.spans.iter()
+        .zip(&layout.operations)
+        .map(|(span, operation)| {
+            Ok(Problem {
+                numbers: layout.column_numbers(*span)?,
+                operation: *operation,
+            })
+        })
+        .collect()
+}
 
-            number_string.trim().parse().expect("Expected a number")
-        });
+#[derive(Debug, PartialEq)]
+enum RenderError {
+    /// The row-wise layout needs the same number of numbers in every problem.
+    UnevenProblems,
+    /// A problem without numbers has no columns to sit above its operation.
+    EmptyProblem(usize),
+}
 
-        for number in numbers {
-            problems[i].numbers.push(number);
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::UnevenProblems => write!(f, "row-wise layout needs the same number of numbers in every problem"),
+            RenderError::EmptyProblem(problem) => write!(f, "problem {problem} has no numbers"),
         }
     }
+}
 
-    problems
+/// Lays the problems out side by side in the row-wise layout `parse1` reads:
+/// number `i` of every problem on line `i`, right-aligned, with the
+/// operations on the last line.
+fn render_rows(problems: &[Problem]) -> Result<String, RenderError> {
+    let height = problems.first().map_or(0, |problem| problem.numbers.len());
+    if let Some(problem) = problems.iter().position(|problem| problem.numbers.is_empty()) {
+        return Err(RenderError::EmptyProblem(problem));
+    }
+    if problems.iter().any(|problem| problem.numbers.len() != height) {
+        return Err(RenderError::UnevenProblems);
+    }
+
+    let widths: Vec<usize> = problems.iter()
+        .map(|problem| problem.numbers.iter().map(|number| number.to_string().len()).max().unwrap_or(1))
+        .collect();
+
+    let mut lines: Vec<String> = (0..height)
+        .map(|row| {
+            problems.iter()
+                .zip(&widths)
+                .map(|(problem, width)| format!("{:>width$}", problem.numbers[row]))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+
+    let operations = problems.iter()
+        .zip(&widths)
+        .map(|(problem, width)| format!("{:<width$}", problem.operation.symbol()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    lines.push(operations.trim_end().to_string());
+
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Lays the problems out in the column-wise layout `parse2` reads: every
+/// number is a column of digits read top to bottom, problems are separated
+/// by a blank column, and each operation sits under its problem's first
+/// column. Trailing spaces are trimmed.
+fn render_columns(problems: &[Problem]) -> Result<String, RenderError> {
+    if let Some(problem) = problems.iter().position(|problem| problem.numbers.is_empty()) {
+        return Err(RenderError::EmptyProblem(problem));
+    }
+
+    let digits: Vec<Vec<Vec<char>>> = problems.iter()
+        .map(|problem| problem.numbers.iter().map(|number| number.to_string().chars().collect()).collect())
+        .collect();
+    let height = digits.iter().flatten().map(Vec::len).max().unwrap_or(0);
+
+    let mut lines = vec![String::new(); height + 1];
+    for (i, (problem, columns)) in problems.iter().zip(&digits).enumerate() {
+        if i > 0 {
+            lines.iter_mut().for_each(|line| line.push(' '));
+        }
+
+        for (row, line) in lines[..height].iter_mut().enumerate() {
+            line.extend(columns.iter().map(|column| column.get(row).copied().unwrap_or(' ')));
+        }
+
+        let operations = &mut lines[height];
+        operations.push(problem.operation.symbol());
+        operations.extend(std::iter::repeat_n(' ', columns.len() - 1));
+    }
+
+    Ok(lines.iter().map(|line| line.trim_end().to_string() + "\n").collect())
+}
+
+/// One problem read both ways, for auditing the two interpretations.
+struct ProblemReport {
+    index: usize,
+    span: ColumnSpan,
+    operation: Operation,
+    row_wise: Vec<u64>,
+    column_wise: Vec<u64>,
+    row_result: Result<BigInt, SolveError>,
+    column_result: Result<BigInt, SolveError>,
+}
+
+impl ProblemReport {
+    /// How far apart the two results are, if both could be computed.
+    fn difference(&self) -> Option<BigUint> {
+        match (&self.row_result, &self.column_result) {
+            (Ok(row), Ok(column)) => Some((row - column).magnitude().clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Reads every problem both ways and solves each reading, checked in `u64`
+/// unless `exact` is set.
+fn build_report(layout: &Layout, exact: bool) -> Result<Vec<ProblemReport>, LayoutError> {
+    layout.spans.iter()
+        .zip(&layout.operations)
+        .enumerate()
+        .map(|(index, (span, operation))| {
+            let row_wise = layout.row_numbers(*span)?;
+            let column_wise = layout.column_numbers(*span)?;
+
+            let solve = |numbers: &[u64]| {
+                let problem = Problem { numbers: numbers.to_vec(), operation: *operation };
+                if exact { problem.solve_exact() } else { problem.solve().map(BigInt::from) }
+            };
+
+            Ok(ProblemReport {
+                index,
+                span: *span,
+                operation: *operation,
+                row_result: solve(&row_wise),
+                column_result: solve(&column_wise),
+                row_wise,
+                column_wise,
+            })
+        })
+        .collect()
+}
+
+/// Prints every problem with both readings, marking the `highlight` problems
+/// whose two results differ the most.
+fn print_report(reports: &[ProblemReport], highlight: usize) {
+    let mut by_difference: Vec<(usize, BigUint)> = reports.iter()
+        .filter_map(|report| report.difference().map(|difference| (report.index, difference)))
+        .collect();
+    by_difference.sort_by(|(i1, d1), (i2, d2)| d2.cmp(d1).then(i1.cmp(i2)));
+    by_difference.truncate(highlight);
+
+    let show = |result: &Result<BigInt, SolveError>| match result {
+        Ok(result) => result.to_string(),
+        Err(e) => format!("error: {e}"),
+    };
+
+    for report in reports {
+        println!("problem {} ({}) '{}'", report.index, report.span, report.operation.symbol());
+        println!("  row-wise:    {:?} = {}", report.row_wise, show(&report.row_result));
+        println!("  column-wise: {:?} = {}", report.column_wise, show(&report.column_result));
+
+        match (report.difference(), by_difference.iter().position(|(index, _)| *index == report.index)) {
+            (Some(difference), Some(rank)) => println!("  difference:  {difference}  <-- #{} largest", rank + 1),
+            (Some(difference), None) => println!("  difference:  {difference}"),
+            (None, _) => {}
+        }
+    }
+}
+
+/// Prints the worksheet total, checked in `u64` unless `exact` is set.
+fn report(part: &str, problems: &[Problem], exact: bool) {
+    let total = if exact {
+        solve_exact(problems).map(|total| total.to_string())
+    } else {
+        solve(problems).map(|total| total.to_string())
+    };
+
+    match total {
+        Ok(total) => println!("{part}: {total}"),
+        Err(e) if e.error == SolveError::Overflow => println!("{part}: {e} (rerun with --exact)"),
+        Err(e) => println!("{part}: {e}"),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Rows,
+    Columns,
+}
+
+struct Options {
+    exact: bool,
+    render: Option<Direction>,
+    report: bool,
+    highlight: usize,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        exact: false,
+        render: None,
+        report: false,
+        highlight: 3,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("Expected a value after '{arg}'"));
+
+        match arg.as_str() {
+            "--exact" => options.exact = true,
+            "--render" => {
+                options.render = Some(match value().as_str() {
+                    "rows" => Direction::Rows,
+                    "columns" => Direction::Columns,
+                    other => panic!("Expected 'rows' or 'columns', got '{other}'"),
+                });
+            }
+            "--report" => options.report = true,
+            "--highlight" => options.highlight = value().parse().expect("Expected a number of problems"),
+            _ => panic!("Unknown argument '{arg}'"),
+        }
+    }
+
+    options
 }
 
 fn main() {
+    let options = parse_args();
     let input = read_input();
 
     let parsed1 = parse1(&input);
+    report("part1", &parsed1, options.exact);
+
     let parsed2 = parse2(&input);
+    match &parsed2 {
+        Ok(parsed2) => report("part2", parsed2, options.exact),
+        Err(e) => println!("part2: {e}"),
+    }
 
-    println!("part1: {}", solve(&parsed1));
-    println!("part2: {}", solve(&parsed2));
+    // Re-renders the worksheet as read by the part whose layout is asked for.
+    let rendered = match (options.render, &parsed2) {
+        (Some(Direction::Rows), _) => Some(render_rows(&parsed1)),
+        (Some(Direction::Columns), Ok(parsed2)) => Some(render_columns(parsed2)),
+        _ => None,
+    };
+    match rendered {
+        Some(Ok(worksheet)) => print!("{worksheet}"),
+        Some(Err(e)) => println!("render: {e}"),
+        None => {}
+    }
+
+    if options.report {
+        match parse_layout(&input).and_then(|layout| build_report(&layout, options.exact)) {
+            Ok(reports) => print_report(&reports, options.highlight),
+            Err(e) => println!("report: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Renders `problems` in every layout they fit and parses them back.
+    fn assert_round_trips(problems: &[Problem]) {
+        let height = problems[0].numbers.len();
+        if problems.iter().all(|problem| problem.numbers.len() == height) {
+            let rows = render_rows(problems).unwrap();
+            assert_eq!(parse1(&rows), problems, "row-wise round trip failed for\n{rows}");
+        }
+
+        let columns = render_columns(problems).unwrap();
+        assert_eq!(parse2(&columns).as_deref(), Ok(problems), "column-wise round trip failed for\n{columns}");
+    }
+
+    fn problem(operation: Operation, numbers: &[u64]) -> Problem {
+        Problem { numbers: numbers.to_vec(), operation }
+    }
+
+    #[test]
+    fn random_worksheets_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+
+        for _ in 0..500 {
+            let count = rng.gen_range(1, 7);
+            let height = rng.gen_range(1, 6);
+            let mut problems: Vec<Problem> = (0..count)
+                .map(|_| Problem {
+                    // A random digit count, so short and long numbers mix.
+                    numbers: (0..height)
+                        .map(|_| {
+                            let digits = rng.gen_range(1, 20);
+                            rng.gen_range(0, 10u64.pow(digits))
+                        })
+                        .collect(),
+                    operation: Operation::ALL[rng.gen_range(0, Operation::ALL.len())],
+                })
+                .collect();
+            assert_round_trips(&problems);
+
+            // The column-wise layout also allows a different count per problem.
+            for problem in &mut problems {
+                problem.numbers.truncate(rng.gen_range(1, height + 1));
+            }
+            assert_round_trips(&problems);
+        }
+    }
+
+    #[test]
+    fn ragged_columns_round_trip() {
+        assert_round_trips(&[
+            problem(Operation::Add, &[1, 22, 333]),
+            problem(Operation::Multiply, &[4444]),
+            problem(Operation::Subtract, &[55, 6]),
+        ]);
+    }
+
+    #[test]
+    fn zeros_round_trip() {
+        assert_round_trips(&[problem(Operation::Add, &[0, 0]), problem(Operation::Multiply, &[0, 10])]);
+        assert_round_trips(&[problem(Operation::Max, &[0])]);
+    }
+
+    #[test]
+    fn nineteen_digit_numbers_round_trip() {
+        assert_round_trips(&[
+            problem(Operation::Add, &[1_000_000_000_000_000_000, 9_999_999_999_999_999_999]),
+            problem(Operation::Min, &[7, 1_234_567_890_123_456_789]),
+        ]);
+    }
+
+    #[test]
+    fn huge_exact_powers_are_refused() {
+        let problem = problem(Operation::Power, &[9999, 9999, 9999]);
+        assert_eq!(problem.solve_exact(), Err(SolveError::TooLarge));
+
+        assert_eq!(Operation::Power.apply_exact(BigInt::from(1u8), 1 << 30), Ok(BigInt::from(1u8)));
+        assert_eq!(Operation::Power.apply_exact(BigInt::from(2u8), 1000), Ok(BigInt::from(2u8).pow(1000)));
+    }
+
+    #[test]
+    fn report_on_huge_powers_finishes() {
+        let layout = parse_layout("9999\n9999\n9999\n^\n").unwrap();
+
+        let checked = build_report(&layout, false).unwrap();
+        assert_eq!(checked[0].row_result, Err(SolveError::Overflow));
+
+        let exact = build_report(&layout, true).unwrap();
+        assert_eq!(exact[0].row_result, Err(SolveError::TooLarge));
+        assert_eq!(exact[0].column_result, Err(SolveError::TooLarge));
+    }
+
+    #[test]
+    fn every_operation_round_trips() {
+        let problems: Vec<Problem> = Operation::ALL
+            .iter()
+            .map(|operation| problem(*operation, &[12, 3]))
+            .collect();
+        assert_round_trips(&problems);
+
+        for operation in Operation::ALL {
+            assert_round_trips(&[problem(operation, &[5])]);
+        }
+    }
+
+    #[test]
+    fn checked_operations_fail_outside_u64() {
+        assert_eq!(Operation::Subtract.apply(3, 4), Err(SolveError::Overflow));
+        assert_eq!(Operation::Subtract.apply(4, 4), Ok(0));
+        assert_eq!(Operation::Divide.apply(7, 0), Err(SolveError::DivisionByZero));
+        assert_eq!(Operation::Divide.apply(7, 2), Ok(3));
+        assert_eq!(problem(Operation::Divide, &[0, 0]).solve(), Err(SolveError::DivisionByZero));
+        assert_eq!(problem(Operation::Subtract, &[10, 2, 9]).solve(), Err(SolveError::Overflow));
+    }
+
+    #[test]
+    fn numbers_fold_from_the_left() {
+        assert_eq!(problem(Operation::Subtract, &[10, 2, 3]).solve(), Ok(5));
+        assert_eq!(problem(Operation::Power, &[2, 3, 2]).solve(), Ok(64));
+        assert_eq!(problem(Operation::Divide, &[100, 5, 2]).solve(), Ok(10));
+        assert_eq!(problem(Operation::Concat, &[1, 20, 3]).solve(), Ok(1203));
+
+        assert_eq!(problem(Operation::Subtract, &[2, 3, 10]).solve_exact(), Ok(BigInt::from(-11)));
+        assert_eq!(problem(Operation::Power, &[2, 3, 2]).solve_exact(), Ok(BigInt::from(64)));
+    }
+
+    #[test]
+    fn concat_reaches_the_edge_of_u64() {
+        assert_eq!(Operation::Concat.apply(0, 10_000_000_000_000_000_000), Ok(10_000_000_000_000_000_000));
+        assert_eq!(Operation::Concat.apply(0, u64::MAX), Ok(u64::MAX));
+        assert_eq!(Operation::Concat.apply(1, u64::MAX), Err(SolveError::Overflow));
+        assert_eq!(Operation::Concat.apply(1, 8_446_744_073_709_551_615), Ok(18_446_744_073_709_551_615));
+        assert_eq!(Operation::Concat.apply(1, 8_446_744_073_709_551_616), Err(SolveError::Overflow));
+        assert_eq!(Operation::Concat.apply(u64::MAX, 0), Err(SolveError::Overflow));
+        assert_eq!(Operation::Concat.apply(12, 0), Ok(120));
+    }
+
+    #[test]
+    fn layout_errors_point_at_the_column() {
+        let span = |start, end| ColumnSpan { start, end };
+
+        assert_eq!(parse_layout("12 4\n3  5\n  + *").err(), Some(LayoutError::MisalignedOperation { column: 2 }));
+        assert_eq!(
+            parse_layout("12 4\n3  5\n+* *").err(),
+            Some(LayoutError::ExtraOperation { column: 1, span: span(0, 2) })
+        );
+        assert_eq!(parse_layout("12 4\n3  5\n+").err(), Some(LayoutError::MissingOperation { span: span(3, 4) }));
+        assert_eq!(
+            parse_layout("12 4\n3  5\n+  %").err(),
+            Some(LayoutError::UnknownOperation { column: 3, symbol: '%' })
+        );
+        assert_eq!(parse_layout("\n\n").err(), Some(LayoutError::MissingOperationLine));
+    }
+
+    #[test]
+    fn invalid_numbers_point_at_their_start() {
+        let layout = parse_layout("12 4\n3x 5\n+  *").unwrap();
+        let invalid = |line, column, text: &str| LayoutError::InvalidNumber { line, column, text: text.to_string() };
+
+        assert_eq!(layout.row_numbers(layout.spans[0]), Err(invalid(1, 0, "3x")));
+        assert_eq!(layout.column_numbers(layout.spans[0]), Err(invalid(0, 1, "2x")));
+        assert_eq!(layout.row_numbers(layout.spans[1]), Ok(vec![4, 5]));
+
+        let layout = parse_layout("  1\n 1x5\n +").unwrap();
+        assert_eq!(layout.row_numbers(layout.spans[0]), Err(invalid(1, 1, "1x5")));
+        assert_eq!(layout.column_numbers(layout.spans[0]), Err(invalid(0, 2, "1x")));
+    }
+
+    #[test]
+    fn trimmed_trailing_spaces_are_padded_back() {
+        let padded = "123 328\n 45 64 \n  6 98 \n*   +  ";
+        let trimmed = "123 328\n 45 64\n  6 98\n*   +";
+
+        let layout = parse_layout(trimmed).unwrap();
+        assert!(layout.rows.iter().all(|row| row.len() == 7));
+        assert_eq!(layout.spans, [ColumnSpan { start: 0, end: 3 }, ColumnSpan { start: 4, end: 7 }]);
+        assert_eq!(parse2(trimmed), parse2(padded));
+        assert_eq!(
+            parse2(trimmed).unwrap(),
+            [problem(Operation::Multiply, &[1, 24, 356]), problem(Operation::Add, &[369, 248, 8])]
+        );
+    }
 }
@@ -1,10 +1,21 @@
 use std::{
-    collections::{HashMap, HashSet},
-    ops::AddAssign,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Write},
+    str::FromStr,
 };
 
-fn read_input() -> Vec<String> {
-    let input = include_str!("../input");
+use image::{Rgb, RgbImage};
+use imageproc::drawing::draw_filled_rect_mut;
+use imageproc::rect::Rect;
+use num_bigint::BigUint;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+fn read_input(path: Option<&str>) -> Vec<String> {
+    let input = match path {
+        Some(path) => std::fs::read_to_string(path).expect("Expected readable input file"),
+        None => include_str!("../input").to_string(),
+    };
+
     input
         .lines()
         .map(std::string::ToString::to_string)
@@ -12,20 +23,253 @@ fn read_input() -> Vec<String> {
 }
 
 fn main() {
-    let lines = read_input();
+    let options = parse_args();
+    let lines = read_input(options.input.as_deref());
     let parsed = parse(&lines);
 
-    println!("part1: {}", part1(&parsed));
-    println!("part2: {}", part2(&parsed));
+    println!("part1: {}", part1(&parsed, options.edge));
+    let timelines = match options.modulus {
+        Some(modulus) => part2_modulo(&parsed, options.edge, modulus).map(|count| format!("{count} (mod {modulus})")),
+        None => part2(&parsed, options.edge).map(|count| count.to_string()),
+    };
+    match timelines {
+        Ok(timelines) => println!("part2: {timelines}"),
+        Err(e) => println!("part2: {e}"),
+    }
+
+    if options.verify {
+        assert!(parsed.is_downward_only(), "Only downward-only diagrams can be checked against the row simulation");
+
+        let graph = BeamGraph::new(&parsed, options.edge);
+        assert_eq!(part1_rows(&parsed, options.edge), graph.splitters_hit().len(), "Row and state simulations disagree on part1");
+
+        let order = graph.topological_order().expect("Downward-only diagrams cannot loop");
+        let zero = BigUint::ZERO;
+        let one = BigUint::from(1u8);
+        assert_eq!(
+            count_timelines_rows(&parsed, options.edge, zero.clone(), one.clone()),
+            graph.count_timelines(&order, zero, one),
+            "Row and state simulations disagree on part2"
+        );
+        println!("verify: ok");
+    }
+
+    if options.splitters || options.splitters_csv.is_some() {
+        let report = SplitterReport::new(&parsed, options.edge);
+
+        if options.splitters {
+            print!("{}", report.render(&parsed));
+        }
+        if let Some(path) = &options.splitters_csv {
+            std::fs::write(path, report.to_csv()).expect("Expected writable CSV path");
+        }
+    }
+
+    if options.ansi || options.render.is_some() {
+        let heatmap = Heatmap::new(&parsed, options.edge);
+
+        if options.ansi {
+            print!("{}", heatmap.ansi(&parsed));
+        }
+        if let Some(path) = &options.render {
+            heatmap.save(&parsed, path, options.cell_size);
+        }
+    }
+
+    if options.enumerate.is_some() || options.sample.is_some() || !options.decode.is_empty() {
+        let timelines = match Timelines::new(&parsed, options.edge) {
+            Ok(timelines) => timelines,
+            Err(e) => {
+                println!("timelines: {e}");
+                return;
+            }
+        };
+
+        if let Some(count) = options.enumerate {
+            let mut index = BigUint::ZERO;
+            while index < BigUint::from(count) {
+                let Some(choices) = timelines.decode(&index) else {
+                    break;
+                };
+                println!("{index}: {}", format_choices(&choices));
+                index += 1u8;
+            }
+        }
+
+        if let Some(count) = options.sample {
+            let mut rng = StdRng::seed_from_u64(options.seed);
+            for _ in 0..count {
+                println!("sample: {}", format_choices(&timelines.sample(&mut rng)));
+            }
+        }
+
+        for index in &options.decode {
+            match timelines.decode(index) {
+                Some(choices) => println!("{index}: {}", format_choices(&choices)),
+                None => println!("{index}: out of range, there are {} timelines", timelines.len()),
+            }
+        }
+    }
+}
+
+type Coord = (usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    fn offset(self) -> (i64, i64) {
+        match self {
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+        }
+    }
+
+    fn is_vertical(self) -> bool {
+        matches!(self, Direction::Up | Direction::Down)
+    }
+
+    fn reverse(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tile {
+    /// `.` and `S`: beams pass straight on.
+    Empty,
+    /// `^`: a vertical beam continues on both sides of the splitter. Horizontal
+    /// beams pass straight through.
+    Splitter,
+    /// `+`: like `^`, but a vertical beam also continues straight on.
+    ThreeWaySplitter,
+    /// `|`: beams pass straight through in any direction.
+    PassThrough,
+    /// `#`: the beam stops, ending its timeline.
+    Absorber,
+    /// `/`: turns downward beams left, rightward beams up, and so on.
+    Mirror,
+    /// `\`: turns downward beams right, rightward beams down, and so on.
+    BackMirror,
+    /// `<`, `>` and `v`: sends every beam off in one direction, except beams
+    /// travelling the opposite way, which are absorbed.
+    Deflector(Direction),
 }
 
-type Coord = (i64, i64);
+impl Tile {
+    fn from_char(char: char) -> Option<Self> {
+        match char {
+            '.' | 'S' => Some(Tile::Empty),
+            '^' => Some(Tile::Splitter),
+            '+' => Some(Tile::ThreeWaySplitter),
+            '|' => Some(Tile::PassThrough),
+            '#' => Some(Tile::Absorber),
+            '/' => Some(Tile::Mirror),
+            '\\' => Some(Tile::BackMirror),
+            '<' => Some(Tile::Deflector(Direction::Left)),
+            '>' => Some(Tile::Deflector(Direction::Right)),
+            'v' => Some(Tile::Deflector(Direction::Down)),
+            _ => None,
+        }
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            Tile::Empty => '.',
+            Tile::Splitter => '^',
+            Tile::ThreeWaySplitter => '+',
+            Tile::PassThrough => '|',
+            Tile::Absorber => '#',
+            Tile::Mirror => '/',
+            Tile::BackMirror => '\\',
+            Tile::Deflector(Direction::Left) => '<',
+            Tile::Deflector(Direction::Right) => '>',
+            Tile::Deflector(Direction::Down) => 'v',
+            Tile::Deflector(Direction::Up) => unreachable!("No tile deflects beams upward"),
+        }
+    }
+
+    fn is_splitter(self) -> bool {
+        matches!(self, Tile::Splitter | Tile::ThreeWaySplitter)
+    }
+
+    /// Whether a downward beam entering this tile only ever leaves downward.
+    fn keeps_beams_down(self) -> bool {
+        !matches!(
+            self,
+            Tile::Mirror | Tile::BackMirror | Tile::Deflector(Direction::Left | Direction::Right | Direction::Up)
+        )
+    }
+}
 
 #[derive(Debug)]
 struct Diagram {
     start: Coord,
-    splitters: HashSet<Coord>,
+    /// `tiles[y][x]`, with every row padded to `width`.
+    tiles: Vec<Vec<Tile>>,
     height: usize,
+    width: usize,
+}
+
+impl Diagram {
+    /// Whether every beam only ever travels downward, as in the original
+    /// puzzle, so the row-by-row simulation applies.
+    fn is_downward_only(&self) -> bool {
+        self.tiles.iter().flatten().all(|tile| tile.keeps_beams_down())
+    }
+}
+
+/// What happens to a beam that is sent past the left or right edge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edge {
+    /// The beam leaves the manifold and stops.
+    Absorb,
+    /// The beam bounces back into the edge column.
+    Reflect,
+    /// The beam comes back in on the opposite side.
+    Wrap,
+}
+
+impl Edge {
+    /// The column a beam sent to column `x` ends up in, or `None` when it is
+    /// absorbed.
+    fn resolve(self, x: i64, width: usize) -> Option<usize> {
+        let width = width as i64;
+        let x = match self {
+            Edge::Absorb => x,
+            Edge::Reflect if x < 0 => -x - 1,
+            Edge::Reflect if x >= width => 2 * width - 1 - x,
+            Edge::Reflect => x,
+            Edge::Wrap => x.rem_euclid(width),
+        };
+
+        (0..width).contains(&x).then_some(x as usize)
+    }
+}
+
+impl FromStr for Edge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "absorb" => Ok(Edge::Absorb),
+            "reflect" => Ok(Edge::Reflect),
+            "wrap" => Ok(Edge::Wrap),
+            _ => Err(format!("Unknown edge behaviour '{s}'")),
+        }
+    }
 }
 
 fn parse(lines: &[String]) -> Diagram {
@@ -33,92 +277,1093 @@ fn parse(lines: &[String]) -> Diagram {
         .first()
         .and_then(|line| line.find('S'))
         .expect("Expected start in first line");
-    let start = (start as i64, 0);
+    let start = (start, 0);
 
     let height = lines.len();
+    let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
 
-    let splitters = lines
+    let tiles = lines
         .iter()
         .enumerate()
-        .skip(1)
-        .flat_map(|(y, line)| {
-            line.chars()
+        .map(|(y, line)| {
+            let mut row: Vec<Tile> = line
+                .chars()
                 .enumerate()
-                .filter_map(|(x, char)| {
-                    if char == '^' {
-                        Some((x as i64, y as i64))
-                    } else {
-                        None
-                    }
+                .map(|(x, char)| {
+                    Tile::from_char(char).unwrap_or_else(|| panic!("Unknown tile '{char}' at line {}, column {}", y + 1, x + 1))
                 })
-                .collect::<Vec<Coord>>()
+                .collect();
+            row.resize(width, Tile::Empty);
+            row
         })
         .collect();
 
     Diagram {
         start,
-        splitters,
+        tiles,
         height,
+        width,
     }
 }
 
-fn part1(diagram: &Diagram) -> usize {
+/// Calls `f` with every column a downward beam in column `x` continues in
+/// when it reaches row `y`: both sides of a splitter, or straight on. `None`
+/// stands for a beam that is absorbed there.
+fn for_each_next(diagram: &Diagram, edge: Edge, x: usize, y: usize, mut f: impl FnMut(Option<usize>)) {
+    match diagram.tiles[y][x] {
+        Tile::Splitter => {
+            f(edge.resolve(x as i64 - 1, diagram.width));
+            f(edge.resolve(x as i64 + 1, diagram.width));
+        }
+        Tile::ThreeWaySplitter => {
+            f(edge.resolve(x as i64 - 1, diagram.width));
+            f(Some(x));
+            f(edge.resolve(x as i64 + 1, diagram.width));
+        }
+        Tile::Absorber => f(None),
+        Tile::Empty | Tile::PassThrough | Tile::Deflector(Direction::Down) => f(Some(x)),
+        tile => unreachable!("{tile:?} turns beams away from the rows"),
+    }
+}
+
+fn is_split(tile: Tile, direction: Direction) -> bool {
+    tile.is_splitter() && direction.is_vertical()
+}
+
+/// `part1` for downward-only diagrams, one dense row of beams at a time.
+fn part1_rows(diagram: &Diagram, edge: Edge) -> usize {
     let mut splits = 0;
-    let mut beams = HashSet::new();
-    beams.insert(diagram.start);
-    let mut rounds = 0;
+    let mut beams = vec![false; diagram.width];
+    beams[diagram.start.0] = true;
 
-    while rounds < diagram.height {
-        let mut new_beams = HashSet::new();
+    for y in 1..diagram.height {
+        let mut new_beams = vec![false; diagram.width];
 
-        for (beam_x, beam_y) in &beams {
-            if diagram.splitters.contains(&(*beam_x, beam_y + 1)) {
+        for x in (0..diagram.width).filter(|x| beams[*x]) {
+            if is_split(diagram.tiles[y][x], Direction::Down) {
                 splits += 1;
-                new_beams.insert((beam_x - 1, beam_y + 1));
-                new_beams.insert((beam_x + 1, beam_y + 1));
-            } else {
-                new_beams.insert((*beam_x, beam_y + 1));
             }
+
+            for_each_next(diagram, edge, x, y, |new_x| {
+                if let Some(new_x) = new_x {
+                    new_beams[new_x] = true;
+                }
+            });
         }
 
         beams = new_beams;
-
-        rounds += 1;
     }
 
     splits
 }
 
-fn part2(diagram: &Diagram) -> i64 {
-    let mut beams = HashMap::new();
-    beams.insert(diagram.start, 1);
-    let mut rounds = 0;
+/// Counts the splitters that split at least one beam.
+fn part1(diagram: &Diagram, edge: Edge) -> usize {
+    if diagram.is_downward_only() {
+        part1_rows(diagram, edge)
+    } else {
+        BeamGraph::new(diagram, edge).splitters_hit().len()
+    }
+}
 
-    while rounds < diagram.height {
-        let mut new_beams: HashMap<(i64, i64), i64> = HashMap::new();
+/// A number to count timelines in. The count roughly doubles with every
+/// level of splitters, so each kind decides what happens when it gets large.
+trait Count: Clone + PartialEq {
+    /// `None` when the sum no longer fits.
+    fn add(&self, other: &Self) -> Option<Self>;
+}
 
-        for ((beam_x, beam_y), count) in &beams {
-            if diagram.splitters.contains(&(*beam_x, beam_y + 1)) {
-                new_beams
-                    .entry((beam_x - 1, beam_y + 1))
-                    .and_modify(|current_count| current_count.add_assign(count))
-                    .or_insert(*count);
-                new_beams
-                    .entry((beam_x + 1, beam_y + 1))
-                    .and_modify(|current_count| current_count.add_assign(count))
-                    .or_insert(*count);
-            } else {
-                new_beams
-                    .entry((*beam_x, beam_y + 1))
-                    .and_modify(|current_count| current_count.add_assign(count))
-                    .or_insert(*count);
-            }
+impl Count for u64 {
+    fn add(&self, other: &Self) -> Option<Self> {
+        self.checked_add(*other)
+    }
+}
+
+impl Count for BigUint {
+    fn add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+}
+
+/// A count modulo `modulus`, which never overflows.
+#[derive(Clone, PartialEq)]
+struct Modular {
+    value: u64,
+    modulus: u64,
+}
+
+impl Count for Modular {
+    fn add(&self, other: &Self) -> Option<Self> {
+        let value = (u128::from(self.value) + u128::from(other.value)) % u128::from(self.modulus);
+        Some(Modular { value: value as u64, modulus: self.modulus })
+    }
+}
+
+/// Counts every timeline of a downward-only diagram, including those that
+/// end early when a beam is absorbed. Returns `None` if the count does not
+/// fit in `C`.
+fn count_timelines_rows<C: Count>(diagram: &Diagram, edge: Edge, zero: C, one: C) -> Option<C> {
+    let mut beams = vec![zero.clone(); diagram.width];
+    beams[diagram.start.0] = one;
+    let mut absorbed = zero.clone();
+
+    for y in 1..diagram.height {
+        let mut new_beams = vec![zero.clone(); diagram.width];
+        let mut fits = true;
+
+        for (x, count) in beams.iter().enumerate().filter(|(_, count)| **count != zero) {
+            for_each_next(diagram, edge, x, y, |new_x| {
+                let total = match new_x {
+                    Some(new_x) => &mut new_beams[new_x],
+                    None => &mut absorbed,
+                };
+
+                match total.add(count) {
+                    Some(sum) => *total = sum,
+                    None => fits = false,
+                }
+            });
+        }
+
+        if !fits {
+            return None;
         }
 
         beams = new_beams;
+    }
+
+    beams.iter().try_fold(absorbed, |total, count| total.add(count))
+}
+
+/// Counts timelines with the row simulation when the diagram allows it, and
+/// with the beam state graph otherwise.
+fn count_timelines<C: Count>(diagram: &Diagram, edge: Edge, zero: C, one: C) -> Result<Option<C>, Cycle> {
+    if diagram.is_downward_only() {
+        Ok(count_timelines_rows(diagram, edge, zero, one))
+    } else {
+        let graph = BeamGraph::new(diagram, edge);
+        let order = graph.topological_order()?;
+        Ok(graph.count_timelines(&order, zero, one))
+    }
+}
+
+/// Counts in checked `u64` arithmetic, and starts over with arbitrary
+/// precision only if that overflows.
+fn part2(diagram: &Diagram, edge: Edge) -> Result<BigUint, Cycle> {
+    match count_timelines(diagram, edge, 0u64, 1u64)? {
+        Some(count) => Ok(BigUint::from(count)),
+        None => Ok(count_timelines(diagram, edge, BigUint::ZERO, BigUint::from(1u8))?
+            .expect("Arbitrary precision counts always fit")),
+    }
+}
+
+fn part2_modulo(diagram: &Diagram, edge: Edge, modulus: u64) -> Result<u64, Cycle> {
+    assert!(modulus > 0, "Expected a positive modulus");
+
+    let zero = Modular { value: 0, modulus };
+    let one = Modular { value: 1 % modulus, modulus };
+
+    Ok(count_timelines(diagram, edge, zero, one)?
+        .expect("Modular counts always fit")
+        .value)
+}
+
+/// A beam that has just been placed in cell `(x, y)`, heading `direction`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Beam {
+    x: usize,
+    y: usize,
+    direction: Direction,
+}
+
+/// The outcome of moving a beam one cell on.
+struct Step {
+    /// The cell the beam moved into, unless it left the manifold.
+    cell: Option<Coord>,
+    /// Whether the tile in `cell` split the beam.
+    split: bool,
+    /// The beams carrying on from left to right, with `None` for each
+    /// timeline that ends here.
+    next: Vec<Option<Beam>>,
+}
+
+impl Diagram {
+    /// Moves `beam` one cell on and applies the tile it enters. Beams that
+    /// leave through the top or bottom end their timeline; the left and right
+    /// edges follow `edge`.
+    ///
+    /// As in the original puzzle, beams split off sideways land next to the
+    /// splitter without the tile there acting on them.
+    fn step(&self, beam: Beam, edge: Edge) -> Step {
+        let ended = Step { cell: None, split: false, next: vec![None] };
+
+        let (dx, dy) = beam.direction.offset();
+        let y = beam.y as i64 + dy;
+        if !(0..self.height as i64).contains(&y) {
+            return ended;
+        }
+        let y = y as usize;
+
+        let raw_x = beam.x as i64 + dx;
+        let Some(x) = edge.resolve(raw_x, self.width) else {
+            return ended;
+        };
+        let bounced = edge == Edge::Reflect && !(0..self.width as i64).contains(&raw_x);
+        let direction = if bounced { beam.direction.reverse() } else { beam.direction };
+
+        let tile = self.tiles[y][x];
+        let to = |direction| Some(Beam { x, y, direction });
+        let side = |offset: i64| edge.resolve(x as i64 + offset, self.width).map(|x| Beam { x, y, direction });
+
+        let next = match tile {
+            Tile::Splitter if direction.is_vertical() => vec![side(-1), side(1)],
+            Tile::ThreeWaySplitter if direction.is_vertical() => vec![side(-1), to(direction), side(1)],
+            Tile::Empty | Tile::PassThrough | Tile::Splitter | Tile::ThreeWaySplitter => vec![to(direction)],
+            Tile::Absorber => vec![None],
+            Tile::Mirror => vec![to(match direction {
+                Direction::Down => Direction::Left,
+                Direction::Up => Direction::Right,
+                Direction::Right => Direction::Up,
+                Direction::Left => Direction::Down,
+            })],
+            Tile::BackMirror => vec![to(match direction {
+                Direction::Down => Direction::Right,
+                Direction::Up => Direction::Left,
+                Direction::Right => Direction::Down,
+                Direction::Left => Direction::Up,
+            })],
+            Tile::Deflector(towards) if towards == direction.reverse() => vec![None],
+            Tile::Deflector(towards) => vec![to(towards)],
+        };
+
+        Step {
+            cell: Some((x, y)),
+            split: is_split(tile, direction),
+            next,
+        }
+    }
+}
+
+/// Beams that can come back to where they were, so they have infinitely
+/// many timelines.
+#[derive(Debug)]
+struct Cycle {
+    beam: Beam,
+}
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "beams loop forever through ({}, {}) heading {:?}",
+            self.beam.x, self.beam.y, self.beam.direction
+        )
+    }
+}
+
+/// Every beam state reachable from the start, and the step out of each one.
+struct BeamGraph {
+    beams: Vec<Beam>,
+    steps: Vec<Step>,
+    /// `next[i]` lists the indices of the beams carrying on from beam `i`,
+    /// like `steps[i].next`.
+    next: Vec<Vec<Option<usize>>>,
+}
+
+impl BeamGraph {
+    fn new(diagram: &Diagram, edge: Edge) -> Self {
+        let start = Beam { x: diagram.start.0, y: diagram.start.1, direction: Direction::Down };
+
+        let mut index = HashMap::from([(start, 0)]);
+        let mut beams = vec![start];
+        let mut steps = vec![];
+        let mut next = vec![];
+
+        // Beams are appended as they are discovered, so this visits each once.
+        while let Some(beam) = beams.get(steps.len()).copied() {
+            let step = diagram.step(beam, edge);
+
+            next.push(
+                step.next
+                    .iter()
+                    .map(|beam| {
+                        beam.map(|beam| {
+                            *index.entry(beam).or_insert_with(|| {
+                                beams.push(beam);
+                                beams.len() - 1
+                            })
+                        })
+                    })
+                    .collect(),
+            );
+            steps.push(step);
+        }
+
+        BeamGraph { beams, steps, next }
+    }
+
+    fn splitters_hit(&self) -> HashSet<Coord> {
+        self.steps
+            .iter()
+            .filter(|step| step.split)
+            .filter_map(|step| step.cell)
+            .collect()
+    }
+
+    /// The beams ordered so that each comes before every beam it leads to,
+    /// or the first beam found on a loop.
+    fn topological_order(&self) -> Result<Vec<usize>, Cycle> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            New,
+            Open,
+            Done,
+        }
+
+        let mut marks = vec![Mark::New; self.beams.len()];
+        let mut post_order = Vec::with_capacity(self.beams.len());
+        // An explicit stack of (beam, next successor to visit), so that long
+        // beam paths cannot overflow the call stack.
+        let mut stack = vec![(0, 0)];
+        marks[0] = Mark::Open;
+
+        while let Some((beam, successor)) = stack.last_mut() {
+            let beam = *beam;
+            match self.next[beam].get(*successor) {
+                Some(next) => {
+                    *successor += 1;
+
+                    if let Some(next) = *next {
+                        match marks[next] {
+                            Mark::New => {
+                                marks[next] = Mark::Open;
+                                stack.push((next, 0));
+                            }
+                            Mark::Open => return Err(Cycle { beam: self.beams[next] }),
+                            Mark::Done => {}
+                        }
+                    }
+                }
+                None => {
+                    marks[beam] = Mark::Done;
+                    post_order.push(beam);
+                    stack.pop();
+                }
+            }
+        }
+
+        post_order.reverse();
+        Ok(post_order)
+    }
+
+    /// The number of timelines from each beam to wherever it ends, indexed
+    /// like `beams`, or `None` if a count does not fit in `C`.
+    fn timelines_from<C: Count>(&self, order: &[usize], zero: &C, one: &C) -> Option<Vec<C>> {
+        let mut counts = vec![zero.clone(); self.beams.len()];
+
+        for beam in order.iter().rev() {
+            let mut count = zero.clone();
+            for next in &self.next[*beam] {
+                count = count.add(next.map_or(one, |next| &counts[next]))?;
+            }
+            counts[*beam] = count;
+        }
+
+        Some(counts)
+    }
+
+    /// The number of timelines from the start to each beam, indexed like
+    /// `beams`, or `None` if a count does not fit in `C`.
+    fn timelines_to<C: Count>(&self, order: &[usize], zero: &C, one: &C) -> Option<Vec<C>> {
+        let mut counts = vec![zero.clone(); self.beams.len()];
+        counts[0] = one.clone();
+
+        for beam in order {
+            for next in self.next[*beam].iter().flatten() {
+                counts[*next] = counts[*next].add(&counts[*beam])?;
+            }
+        }
+
+        Some(counts)
+    }
+
+    fn count_timelines<C: Count>(&self, order: &[usize], zero: C, one: C) -> Option<C> {
+        self.timelines_from(order, &zero, &one).map(|counts| counts[0].clone())
+    }
+
+    /// Cells any beam passes through: every cell a beam is placed in, which
+    /// includes the start and the cells beside a splitter, and every cell a
+    /// beam moves into, which includes the splitters and absorbers themselves.
+    fn lit_cells(&self) -> HashSet<Coord> {
+        self.beams
+            .iter()
+            .map(|beam| (beam.x, beam.y))
+            .chain(self.steps.iter().filter_map(|step| step.cell))
+            .collect()
+    }
+
+    /// The timelines passing through each lit cell, counted once per visit,
+    /// or `None` when beams loop forever.
+    fn cell_timelines(&self) -> Option<HashMap<Coord, BigUint>> {
+        let order = self.topological_order().ok()?;
+        let zero = BigUint::ZERO;
+        let one = BigUint::from(1u8);
+        let to = self.timelines_to(&order, &zero, &one).expect("Arbitrary precision counts always fit");
+        let from = self.timelines_from(&order, &zero, &one).expect("Arbitrary precision counts always fit");
+
+        let start = (self.beams[0].x, self.beams[0].y);
+        let mut counts = HashMap::from([(start, from[0].clone())]);
+        for (beam, step) in self.steps.iter().enumerate() {
+            if let Some(cell) = step.cell {
+                // Every timeline reaching this beam carries on along each of
+                // the timelines leaving it.
+                *counts.entry(cell).or_default() += &to[beam] * &from[beam];
+            }
+
+            // Beams split off sideways are placed beside the cell they came
+            // from, so only the timelines taking that side visit it.
+            for next in self.next[beam].iter().flatten() {
+                let placed = (self.beams[*next].x, self.beams[*next].y);
+                if step.cell != Some(placed) {
+                    *counts.entry(placed).or_default() += &to[beam] * &from[*next];
+                }
+            }
+        }
+
+        Some(counts)
+    }
+}
+
+/// The way a beam continues from a splitter, in the order timelines are
+/// numbered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Choice {
+    Left,
+    Straight,
+    Right,
+}
+
+impl Choice {
+    fn symbol(self) -> char {
+        match self {
+            Choice::Left => 'L',
+            Choice::Straight => 'S',
+            Choice::Right => 'R',
+        }
+    }
+}
+
+/// Numbers the timelines of a diagram in lexicographic order of their
+/// choices, with left before straight before right.
+struct Timelines {
+    graph: BeamGraph,
+    /// The timelines from each beam, indexed like `graph.beams`.
+    counts: Vec<BigUint>,
+}
+
+impl Timelines {
+    fn new(diagram: &Diagram, edge: Edge) -> Result<Self, Cycle> {
+        let graph = BeamGraph::new(diagram, edge);
+        let order = graph.topological_order()?;
+        let counts = graph
+            .timelines_from(&order, &BigUint::ZERO, &BigUint::from(1u8))
+            .expect("Arbitrary precision counts always fit");
+
+        Ok(Timelines { graph, counts })
+    }
+
+    fn len(&self) -> &BigUint {
+        &self.counts[0]
+    }
+
+    /// The choices made by timeline `index`, or `None` if there are not that
+    /// many timelines. Each way on from a beam covers a block of indices as
+    /// long as the number of timelines down it, so this walks down whichever
+    /// block `index` falls into.
+    fn decode(&self, index: &BigUint) -> Option<Vec<Choice>> {
+        if index >= self.len() {
+            return None;
+        }
+
+        let mut index = index.clone();
+        let mut beam = 0;
+        let mut choices = vec![];
+
+        loop {
+            let next = &self.graph.next[beam];
+
+            let mut chosen = None;
+            for (i, option) in next.iter().enumerate() {
+                let count = option.map_or_else(|| BigUint::from(1u8), |option| self.counts[option].clone());
+                if index < count {
+                    chosen = Some((i, *option));
+                    break;
+                }
+                index -= count;
+            }
+            let (i, option) = chosen.expect("Index within the count of its beam");
+
+            match next.len() {
+                1 => {}
+                2 => choices.push([Choice::Left, Choice::Right][i]),
+                _ => choices.push([Choice::Left, Choice::Straight, Choice::Right][i]),
+            }
+
+            match option {
+                Some(option) => beam = option,
+                None => return Some(choices),
+            }
+        }
+    }
+
+    /// A timeline picked uniformly at random, by decoding a uniformly random
+    /// index. This is the same as taking each way on from a beam with
+    /// probability proportional to its count.
+    fn sample(&self, rng: &mut impl Rng) -> Vec<Choice> {
+        // Rejection sampling keeps the index exactly uniform, and accepts
+        // more than half the candidates.
+        let bits = self.len().bits();
+        let index = loop {
+            let random = (0..bits.div_ceil(64)).fold(BigUint::ZERO, |random, _| (random << 64u32) + rng.gen::<u64>());
+            let candidate = random & ((BigUint::from(1u8) << bits) - 1u8);
+            if candidate < *self.len() {
+                break candidate;
+            }
+        };
+
+        self.decode(&index).expect("Sampled index is in range")
+    }
+}
+
+fn format_choices(choices: &[Choice]) -> String {
+    choices.iter().map(|choice| choice.symbol()).collect()
+}
+
+/// How one splitter in the diagram is used.
+struct SplitterUsage {
+    tile: Tile,
+    /// Whether any beam enters the splitter, including beams passing through
+    /// sideways.
+    reached: bool,
+    /// The distinct beams it splits, counted the way `part1` counts
+    /// splitters.
+    beams: usize,
+    /// The timelines passing through it, counted once per visit the way
+    /// `part2` counts timelines, or `None` when beams loop forever.
+    timelines: Option<BigUint>,
+}
+
+/// The usage of every splitter, keyed by `(y, x)` so it is in reading order.
+struct SplitterReport {
+    splitters: BTreeMap<(usize, usize), SplitterUsage>,
+    /// Cells any beam passes through.
+    lit: HashSet<Coord>,
+}
+
+impl SplitterReport {
+    fn new(diagram: &Diagram, edge: Edge) -> Self {
+        let mut splitters: BTreeMap<_, _> = diagram
+            .tiles
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, tile)| (x, y, *tile)))
+            .filter(|(_, _, tile)| tile.is_splitter())
+            .map(|(x, y, tile)| {
+                let usage = SplitterUsage {
+                    tile,
+                    reached: false,
+                    beams: 0,
+                    timelines: None,
+                };
+                ((y, x), usage)
+            })
+            .collect();
+
+        let graph = BeamGraph::new(diagram, edge);
+        for step in &graph.steps {
+            let Some((x, y)) = step.cell else {
+                continue;
+            };
+
+            if let Some(usage) = splitters.get_mut(&(y, x)) {
+                usage.reached = true;
+                if step.split {
+                    usage.beams += 1;
+                }
+            }
+        }
+
+        if let Some(counts) = graph.cell_timelines() {
+            for ((y, x), usage) in &mut splitters {
+                usage.timelines = Some(counts.get(&(*x, *y)).cloned().unwrap_or_default());
+            }
+        }
+
+        let lit = graph.lit_cells();
+        SplitterReport { splitters, lit }
+    }
+
+    fn unreached(&self) -> impl Iterator<Item = Coord> + '_ {
+        self.splitters
+            .iter()
+            .filter(|(_, usage)| !usage.reached)
+            .map(|((y, x), _)| (*x, *y))
+    }
+
+    fn timelines_text(usage: &SplitterUsage) -> String {
+        usage
+            .timelines
+            .as_ref()
+            .map_or_else(|| "inf".to_string(), BigUint::to_string)
+    }
+
+    /// The diagram with lit cells drawn as `|` and unreached splitters as
+    /// `?`, followed on each row by `x:timelines` for its reached splitters.
+    fn render(&self, diagram: &Diagram) -> String {
+        let mut output = String::new();
+
+        for (y, row) in diagram.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let char = match tile {
+                    _ if (x, y) == diagram.start => 'S',
+                    Tile::Empty if self.lit.contains(&(x, y)) => '|',
+                    tile if tile.is_splitter() && !self.splitters[&(y, x)].reached => '?',
+                    tile => tile.symbol(),
+                };
+                output.push(char);
+            }
+
+            let annotations: Vec<String> = self
+                .splitters
+                .range((y, 0)..(y + 1, 0))
+                .filter(|(_, usage)| usage.reached)
+                .map(|((_, x), usage)| format!("{x}:{}", Self::timelines_text(usage)))
+                .collect();
+            if !annotations.is_empty() {
+                write!(output, "  {}", annotations.join(" ")).unwrap();
+            }
+            output.push('\n');
+        }
+
+        let unreached: Vec<String> = self.unreached().map(|(x, y)| format!("({x}, {y})")).collect();
+        if unreached.is_empty() {
+            output.push_str("every splitter is reached\n");
+        } else {
+            writeln!(output, "never reached: {}", unreached.join(", ")).unwrap();
+        }
+
+        output
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("x,y,tile,reached,beams,timelines\n");
+        for ((y, x), usage) in &self.splitters {
+            writeln!(
+                csv,
+                "{x},{y},{},{},{},{}",
+                usage.tile.symbol(),
+                usage.reached,
+                usage.beams,
+                Self::timelines_text(usage)
+            )
+            .unwrap();
+        }
+
+        csv
+    }
+}
+
+/// The diagram with every lit cell shaded by the timelines passing through
+/// it, on a log scale.
+struct Heatmap {
+    lit: HashSet<Coord>,
+    /// `None` when beams loop forever, in which case every lit cell gets the
+    /// brightest shade.
+    timelines: Option<HashMap<Coord, BigUint>>,
+    /// The log of the largest count, which gets the brightest shade.
+    max_log: f64,
+}
+
+impl Heatmap {
+    const BACKGROUND: Rgb<u8> = Rgb([20, 20, 30]);
+    const DIM: Rgb<u8> = Rgb([40, 30, 110]);
+    const BRIGHT: Rgb<u8> = Rgb([255, 220, 60]);
+    const SPLITTER: Rgb<u8> = Rgb([240, 240, 240]);
+    const TILE: Rgb<u8> = Rgb([130, 130, 130]);
+
+    fn new(diagram: &Diagram, edge: Edge) -> Self {
+        let graph = BeamGraph::new(diagram, edge);
+        let timelines = graph.cell_timelines();
+        let max_log = timelines
+            .iter()
+            .flat_map(|counts| counts.values())
+            .map(log2)
+            .fold(0.0, f64::max);
+
+        Heatmap {
+            lit: graph.lit_cells(),
+            timelines,
+            max_log,
+        }
+    }
+
+    /// The shade of a lit cell, from `DIM` for a single timeline up to
+    /// `BRIGHT` for the busiest cell, or `None` for a dark one.
+    fn shade(&self, cell: Coord) -> Option<Rgb<u8>> {
+        if !self.lit.contains(&cell) {
+            return None;
+        }
+
+        let fraction = match &self.timelines {
+            Some(counts) if self.max_log > 0.0 => counts.get(&cell).map_or(0.0, log2) / self.max_log,
+            _ => 1.0,
+        };
+
+        let Rgb(dim) = Self::DIM;
+        let Rgb(bright) = Self::BRIGHT;
+        Some(Rgb(std::array::from_fn(|i| {
+            (f64::from(dim[i]) + (f64::from(bright[i]) - f64::from(dim[i])) * fraction).round() as u8
+        })))
+    }
+
+    fn tile_colour(tile: Tile) -> Option<Rgb<u8>> {
+        match tile {
+            Tile::Empty => None,
+            tile if tile.is_splitter() => Some(Self::SPLITTER),
+            _ => Some(Self::TILE),
+        }
+    }
+
+    /// The diagram in 24-bit terminal colours, with tiles drawn over the
+    /// shaded background of their cell.
+    fn ansi(&self, diagram: &Diagram) -> String {
+        let mut output = String::new();
+
+        for (y, row) in diagram.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let Rgb([r, g, b]) = self.shade((x, y)).unwrap_or(Self::BACKGROUND);
+                let Rgb([fr, fg, fb]) = Self::tile_colour(*tile).unwrap_or(Self::TILE);
+                let char = if (x, y) == diagram.start { 'S' } else { tile.symbol() };
+                write!(output, "\x1b[38;2;{fr};{fg};{fb};48;2;{r};{g};{b}m{char}").unwrap();
+            }
+            output.push_str("\x1b[0m\n");
+        }
+
+        output
+    }
+
+    /// Writes a PNG with each cell `cell_size` pixels square and tiles drawn
+    /// as an inset square over their cell.
+    fn save(&self, diagram: &Diagram, path: &str, cell_size: u32) {
+        let width = diagram.width as u32 * cell_size;
+        let height = diagram.height as u32 * cell_size;
+        let inset = cell_size / 4;
+
+        let mut img = RgbImage::from_pixel(width, height, Self::BACKGROUND);
+        for (y, row) in diagram.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let (px, py) = ((x as u32 * cell_size) as i32, (y as u32 * cell_size) as i32);
+
+                if let Some(shade) = self.shade((x, y)) {
+                    draw_filled_rect_mut(&mut img, Rect::at(px, py).of_size(cell_size, cell_size), shade);
+                }
+                if let Some(colour) = Self::tile_colour(*tile) {
+                    let size = cell_size - 2 * inset;
+                    let rect = Rect::at(px + inset as i32, py + inset as i32).of_size(size, size);
+                    draw_filled_rect_mut(&mut img, rect, colour);
+                }
+            }
+        }
+
+        img.save(path).expect("Expected writable PNG path");
+    }
+}
+
+/// `log2(count)`, accurate to `f64` precision however large the count.
+fn log2(count: &BigUint) -> f64 {
+    let shift = count.bits().saturating_sub(53);
+    let top = u64::try_from(count >> shift).expect("53 bits fit in u64");
+    (top as f64).log2() + shift as f64
+}
+
+struct Options {
+    input: Option<String>,
+    edge: Edge,
+    modulus: Option<u64>,
+    verify: bool,
+    splitters: bool,
+    splitters_csv: Option<String>,
+    render: Option<String>,
+    cell_size: u32,
+    ansi: bool,
+    enumerate: Option<usize>,
+    sample: Option<usize>,
+    seed: u64,
+    decode: Vec<BigUint>,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        input: None,
+        edge: Edge::Absorb,
+        modulus: None,
+        verify: false,
+        splitters: false,
+        splitters_csv: None,
+        render: None,
+        cell_size: 8,
+        ansi: false,
+        enumerate: None,
+        sample: None,
+        seed: 0x5eed,
+        decode: vec![],
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("Expected a value after '{arg}'"));
+
+        match arg.as_str() {
+            "--input" => options.input = Some(value()),
+            "--modulo" => options.modulus = Some(value().parse().expect("Expected a modulus")),
+            "--edge" => options.edge = value().parse().unwrap_or_else(|e| panic!("{e}")),
+            "--verify" => options.verify = true,
+            "--splitters" => options.splitters = true,
+            "--splitters-csv" => options.splitters_csv = Some(value()),
+            "--render" => options.render = Some(value()),
+            "--cell-size" => {
+                options.cell_size = value().parse().ok().filter(|size| *size > 0).expect("Expected a positive cell size");
+            }
+            "--ansi" => options.ansi = true,
+            "--enumerate" => options.enumerate = Some(value().parse().expect("Expected a number of timelines")),
+            "--sample" => options.sample = Some(value().parse().expect("Expected a number of timelines")),
+            "--seed" => options.seed = value().parse().expect("Expected a seed"),
+            "--decode" => options.decode.push(value().parse().expect("Expected a timeline index")),
+            _ => panic!("Unknown argument '{arg}'"),
+        }
+    }
+
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+.......S.......
+...............
+.......^.......
+...............
+......^.^......
+...............
+.....^.^.^.....
+...............
+....^.^...^....
+...............
+...^.^...^.^...
+...............
+..^...^.....^..
+...............
+.^.^.^.^.^...^.
+...............";
+
+    fn diagram(text: &str) -> Diagram {
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        parse(&lines)
+    }
+
+    fn example() -> Diagram {
+        diagram(EXAMPLE)
+    }
+
+    /// `part1` and `part2` under `edge`, checking that the row and beam state
+    /// simulations agree.
+    fn answers(diagram: &Diagram, edge: Edge) -> (usize, u64) {
+        let graph = BeamGraph::new(diagram, edge);
+        let order = graph.topological_order().unwrap();
+        let rows = count_timelines_rows(diagram, edge, 0u64, 1u64).unwrap();
+
+        assert_eq!(part1_rows(diagram, edge), graph.splitters_hit().len(), "part1 under {edge:?}");
+        assert_eq!(Some(rows), graph.count_timelines(&order, 0u64, 1u64), "part2 under {edge:?}");
+
+        (part1(diagram, edge), rows)
+    }
+
+    #[test]
+    fn cells_beside_splitters_are_lit() {
+        let diagram = example();
+        let report = SplitterReport::new(&diagram, Edge::Absorb);
+
+        assert!(report.lit.contains(&(6, 2)) && report.lit.contains(&(8, 2)));
+        assert_eq!(report.render(&diagram).lines().nth(2), Some("......|^|......  7:40"));
+    }
+
+    #[test]
+    fn cells_beside_splitters_get_timelines() {
+        let diagram = example();
+        let counts = BeamGraph::new(&diagram, Edge::Absorb).cell_timelines().unwrap();
+
+        assert_eq!(counts[&(7, 0)], BigUint::from(40u8));
+        assert_eq!(counts[&(6, 2)], BigUint::from(25u8));
+        assert_eq!(counts[&(8, 2)], BigUint::from(15u8));
+        assert_eq!(counts[&(6, 3)], BigUint::from(25u8));
+
+        let heatmap = Heatmap::new(&diagram, Edge::Absorb);
+        assert!(heatmap.shade((6, 2)).is_some());
+        assert_eq!(heatmap.shade((6, 2)), heatmap.shade((6, 3)));
+    }
+
+    #[test]
+    fn timelines_decode_in_order() {
+        let timelines = Timelines::new(&example(), Edge::Absorb).unwrap();
+        assert_eq!(timelines.len(), &BigUint::from(40u8));
+
+        assert_eq!(timelines.decode(&BigUint::ZERO).map(|choices| format_choices(&choices)).as_deref(), Some("LLLLLLL"));
+        assert_eq!(timelines.decode(&BigUint::from(39u8)).map(|choices| format_choices(&choices)).as_deref(), Some("RRRRRRR"));
+        assert_eq!(timelines.decode(&BigUint::from(40u8)), None);
 
-        rounds += 1;
+        let mut rng = StdRng::seed_from_u64(1);
+        let decoded: Vec<_> = (0..40u8).map(|index| timelines.decode(&BigUint::from(index)).unwrap()).collect();
+        for _ in 0..100 {
+            assert!(decoded.contains(&timelines.sample(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn one_column_splits_only_off_the_edges() {
+        let diagram = diagram("S\n^\n.\n^");
+
+        assert_eq!(answers(&diagram, Edge::Absorb), (1, 2));
+        assert_eq!(answers(&diagram, Edge::Reflect), (2, 4));
+        assert_eq!(answers(&diagram, Edge::Wrap), (2, 4));
+    }
+
+    #[test]
+    fn beams_past_the_right_edge() {
+        let diagram = diagram("..S\n..^\n...\n^..");
+
+        // The right half of the first split is lost, bounces back into the
+        // splitter's own column, or wraps round onto the splitter on the left.
+        assert_eq!(answers(&diagram, Edge::Absorb), (1, 2));
+        assert_eq!(answers(&diagram, Edge::Reflect), (1, 2));
+        assert_eq!(answers(&diagram, Edge::Wrap), (2, 3));
+    }
+
+    /// A full pyramid of `levels` levels of splitters under the start, like
+    /// the example but with every splitter present, so every timeline splits
+    /// on every level.
+    fn pyramid(levels: usize) -> Diagram {
+        let width = 2 * levels + 3;
+        let centre = levels + 1;
+
+        let mut lines = vec![format!("{}S{}", ".".repeat(centre), ".".repeat(centre)), ".".repeat(width)];
+        for level in 0..levels {
+            let mut row = vec!['.'; width];
+            for i in 0..=level {
+                row[centre - level + 2 * i] = '^';
+            }
+            lines.push(row.into_iter().collect());
+            lines.push(".".repeat(width));
+        }
+
+        parse(&lines)
+    }
+
+    #[test]
+    fn tall_pyramids_overflow_u64() {
+        let fits = pyramid(63);
+        assert_eq!(count_timelines(&fits, Edge::Absorb, 0u64, 1u64).unwrap(), Some(1 << 63));
+
+        let diagram = pyramid(70);
+        let expected = BigUint::from(1u8) << 70;
+        assert_eq!(part1(&diagram, Edge::Absorb), 70 * 71 / 2);
+        assert_eq!(count_timelines(&pyramid(64), Edge::Absorb, 0u64, 1u64).unwrap(), None);
+        assert_eq!(count_timelines(&diagram, Edge::Absorb, 0u64, 1u64).unwrap(), None);
+        assert_eq!(part2(&diagram, Edge::Absorb).unwrap(), expected);
+
+        let graph = BeamGraph::new(&diagram, Edge::Absorb);
+        let order = graph.topological_order().unwrap();
+        assert_eq!(graph.count_timelines(&order, 0u64, 1u64), None);
+        assert_eq!(graph.count_timelines(&order, BigUint::ZERO, BigUint::from(1u8)), Some(expected.clone()));
+
+        for modulus in [1, 2, 3, 1_000_000_007, u64::MAX] {
+            let remainder = (&expected % modulus).to_u64_digits().first().copied().unwrap_or(0);
+            assert_eq!(part2_modulo(&diagram, Edge::Absorb, modulus).unwrap(), remainder, "mod {modulus}");
+        }
+    }
+
+    /// Where a beam heading `direction` goes after entering the centre of a
+    /// 3x3 diagram holding `tile`.
+    fn through_centre(tile: char, direction: Direction) -> Vec<Option<Beam>> {
+        let diagram = diagram(&format!("S..\n.{tile}.\n..."));
+        let (dx, dy) = direction.offset();
+        let beam = Beam { x: (1 - dx) as usize, y: (1 - dy) as usize, direction };
+
+        let step = diagram.step(beam, Edge::Absorb);
+        assert_eq!(step.cell, Some((1, 1)));
+        step.next
     }
 
-    beams.values().sum()
+    fn leaving(direction: Direction) -> Vec<Option<Beam>> {
+        vec![Some(Beam { x: 1, y: 1, direction })]
+    }
+
+    #[test]
+    fn mirrors_turn_every_direction() {
+        use Direction::*;
+
+        for (from, to) in [(Down, Left), (Up, Right), (Right, Up), (Left, Down)] {
+            assert_eq!(through_centre('/', from), leaving(to), "/ heading {from:?}");
+        }
+        for (from, to) in [(Down, Right), (Up, Left), (Right, Down), (Left, Up)] {
+            assert_eq!(through_centre('\\', from), leaving(to), "\\ heading {from:?}");
+        }
+    }
+
+    #[test]
+    fn deflectors_absorb_beams_heading_back() {
+        use Direction::*;
+
+        for (tile, towards) in [('<', Left), ('>', Right), ('v', Down)] {
+            for direction in [Up, Down, Left, Right] {
+                let expected = if direction == towards.reverse() { vec![None] } else { leaving(towards) };
+                assert_eq!(through_centre(tile, direction), expected, "{tile} heading {direction:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn three_way_splitters_keep_the_middle_beam() {
+        let beside = |x| Some(Beam { x, y: 1, direction: Direction::Down });
+        assert_eq!(through_centre('+', Direction::Down), [beside(0), beside(1), beside(2)]);
+        assert_eq!(through_centre('+', Direction::Right), leaving(Direction::Right));
+
+        let diagram = diagram("..S..\n.....\n..+..\n.....");
+        assert_eq!(answers(&diagram, Edge::Absorb), (1, 3));
+        assert_eq!(count_timelines(&diagram, Edge::Absorb, 0u64, 1u64).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn loops_are_reported_as_cycles() {
+        // The splitter sends a beam round the mirrors and back through itself.
+        let diagram = diagram(".S.\n...\n/^\\\n...\n\\./");
+
+        assert!(!diagram.is_downward_only());
+        assert!(BeamGraph::new(&diagram, Edge::Absorb).topological_order().is_err());
+        assert!(count_timelines(&diagram, Edge::Absorb, 0u64, 1u64).is_err());
+        assert!(part2(&diagram, Edge::Absorb).is_err());
+        assert!(part2_modulo(&diagram, Edge::Absorb, 7).is_err());
+        assert_eq!(part1(&diagram, Edge::Absorb), 1);
+    }
+
+    #[test]
+    fn beam_graph_matches_rows_on_the_example() {
+        let diagram = example();
+
+        assert_eq!(answers(&diagram, Edge::Absorb), (21, 40));
+        for edge in [Edge::Reflect, Edge::Wrap] {
+            answers(&diagram, edge);
+        }
+    }
 }
@@ -1,9 +1,13 @@
-use std::collections::{HashMap, HashSet};
+use std::{cmp::Reverse, collections::BinaryHeap};
 
 type Coord = (i64, i64, i64);
 
-fn read_input() -> Vec<String> {
-    let input = include_str!("../input");
+fn read_input(path: Option<&str>) -> Vec<String> {
+    let input = match path {
+        Some(path) => std::fs::read_to_string(path).expect("Expected readable input file"),
+        None => include_str!("../input").to_string(),
+    };
+
     input
         .lines()
         .map(std::string::ToString::to_string)
@@ -12,174 +16,457 @@ fn read_input() -> Vec<String> {
 
 
 fn main() {
-    let lines = read_input();
-    let parsed = parse(&lines);
+    let options = parse_args();
+    let lines = read_input(options.input.as_deref());
+    let points = parse(&lines);
+
+    println!("part1: {}", part1(&points, options.connections));
+    println!("part2: {}", part2(&points));
+
+    if options.verify {
+        let brute_force = pairs_by_distance(&points);
+        assert!(
+            ClosestPairs::new(&points).eq(brute_force.iter().copied()),
+            "k-d tree and brute force order pairs differently"
+        );
+
+        let mut circuits = Circuits::new(points.len());
+        for (a, b) in brute_force.iter().take(options.connections) {
+            circuits.connect(*a, *b);
+        }
+        assert_eq!(
+            part1(&points, options.connections),
+            circuits.sizes().iter().take(3).product::<usize>(),
+            "k-d tree and brute force disagree on part1"
+        );
+
+        let mut circuits = Circuits::new(points.len());
+        let last = brute_force
+            .into_iter()
+            .find(|(a, b)| circuits.connect(*a, *b) && circuits.count == 1)
+            .map(|(a, b)| points[a].0 * points[b].0);
+        assert_eq!(Some(part2(&points)), last, "k-d tree and brute force disagree on part2");
+        println!("verify: ok");
+    }
 
-    println!("part1: {}", part1(&parsed));
-    println!("part2: {}", part2(&parsed));
+    if let Some(connections) = options.sizes {
+        let circuits = connect(&points, connections);
+        let sizes: Vec<String> = circuits.sizes().iter().map(usize::to_string).collect();
+        println!("sizes after {connections} connections: {}", sizes.join(" "));
+    }
 }
 
-fn parse(lines: &[String]) -> HashSet<Coord> {
-    lines.iter()
+/// Coordinates must stay below this in magnitude, so that squared distances
+/// fit in an `i128`.
+const COORD_LIMIT: i64 = 1 << 61;
+
+/// The distinct points, sorted by x, then y, then z, so that comparing point
+/// indices compares the points themselves.
+fn parse(lines: &[String]) -> Vec<Coord> {
+    let coordinate = |value: &str| {
+        let value: i64 = value.parse().unwrap();
+        assert!(value.abs() < COORD_LIMIT, "Coordinate {value} is out of range");
+        value
+    };
+
+    let mut points: Vec<Coord> = lines.iter()
         .map(|line| {
             let split: Vec<&str> = line.split(',').collect();
-            (split[0].parse().unwrap(), split[1].parse().unwrap(), split[2].parse().unwrap())
+            (coordinate(split[0]), coordinate(split[1]), coordinate(split[2]))
         })
-        .collect()
+        .collect();
+    points.sort_unstable();
+    points.dedup();
+
+    points
 }
 
-fn get_distance(point_a: &Coord, point_b: &Coord) -> f64 {
-    let x =
-        (point_a.0 - point_b.0).pow(2) +
-        (point_a.1 - point_b.1).pow(2) +
-        (point_a.2 - point_b.2).pow(2);
+/// The exact squared distance. Comparing these instead of rounded square
+/// roots keeps nearly equal distances apart.
+fn squared_distance(point_a: &Coord, point_b: &Coord) -> i128 {
+    let square = |a: i64, b: i64| (i128::from(a) - i128::from(b)).pow(2);
 
-    let y = x  as f64;
+    square(point_a.0, point_b.0) +
+    square(point_a.1, point_b.1) +
+    square(point_a.2, point_b.2)
+}
 
-    (y).sqrt()
+/// Circuits of junction boxes, tracked as disjoint sets of point indices with
+/// path compression and union by size.
+struct Circuits {
+    parent: Vec<usize>,
+    /// The number of boxes in each circuit, kept up to date for roots only.
+    size: Vec<usize>,
+    count: usize,
 }
 
-fn are_connected(point_a: &Coord, point_b: &Coord, connections: &HashMap<Coord, HashSet<Coord>>) -> bool {
-    if let Some(connected_to_a) = connections.get(point_a) {
-        connected_to_a.contains(point_b)
-    } else {
-        false
+impl Circuits {
+    /// Every box in a circuit of its own.
+    fn new(len: usize) -> Self {
+        Circuits {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+            count: len,
+        }
     }
-}
 
-fn get_circuit(key: &Coord, connections: &HashMap<Coord, HashSet<Coord>>) -> HashSet<Coord> {
-    let mut stack = vec![key];
-    let mut circuit = HashSet::from([*key]);
+    /// The root of the circuit containing `point`.
+    fn find(&mut self, point: usize) -> usize {
+        let mut root = point;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
 
-    while let Some(node) = stack.pop() {
-        if let Some(connected) = connections.get(node) {
-            for c in connected {
-                if !circuit.contains(c) {
-                    stack.push(c);
-                }
+        // Point everything on the way straight at the root.
+        let mut point = point;
+        while self.parent[point] != root {
+            let next = self.parent[point];
+            self.parent[point] = root;
+            point = next;
+        }
 
-                circuit.insert(*c);
-            }
+        root
+    }
+
+    /// Joins the circuits of `a` and `b`, hanging the smaller one under the
+    /// larger. Returns `false` if they were already the same circuit.
+    fn connect(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
         }
+
+        let (large, small) = if self.size[a] >= self.size[b] { (a, b) } else { (b, a) };
+        self.parent[small] = large;
+        self.size[large] += self.size[small];
+        self.count -= 1;
+
+        true
     }
 
-    circuit
+    /// The size of every circuit, largest first.
+    fn sizes(&self) -> Vec<usize> {
+        let mut sizes: Vec<usize> = (0..self.parent.len())
+            .filter(|point| self.parent[*point] == *point)
+            .map(|root| self.size[root])
+            .collect();
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+
+        sizes
+    }
 }
 
-fn find_circuits(connections: &mut HashMap<Coord, HashSet<Coord>>) -> Vec<HashSet<Coord>> {
-    let keys: Vec<Coord> = connections.keys().cloned().collect();
-    let mut seen = HashSet::new();
+/// Every pair of point indices `(a, b)` with `a < b`, closest first, by
+/// comparing them all. Only used to check `ClosestPairs`.
+///
+/// Pairs at the same distance are ordered by their lower point, then their
+/// higher point. As points are sorted, that is the order of `(a, b)`, so the
+/// order never depends on how the input was read.
+fn pairs_by_distance(points: &[Coord]) -> Vec<(usize, usize)> {
+    let mut distances = vec![];
+    for a in 0..points.len() {
+        for b in a + 1..points.len() {
+            distances.push((squared_distance(&points[a], &points[b]), a, b));
+        }
+    }
+
+    distances.sort_unstable();
+
+    distances.into_iter().map(|(_, a, b)| (a, b)).collect()
+}
 
-    let mut circuits = vec![];
+struct KdNode {
+    point: usize,
+    /// Opposite corners of the box around every point in this subtree.
+    min: Coord,
+    max: Coord,
+    /// The largest point index in this subtree.
+    max_index: usize,
+    children: [Option<usize>; 2],
+}
 
-    for key in keys {
-        if seen.contains(&key) {
-            continue;
+/// A k-d tree splitting on x, y and z in turn at the median point.
+struct KdTree<'a> {
+    points: &'a [Coord],
+    nodes: Vec<KdNode>,
+    root: Option<usize>,
+}
+
+impl<'a> KdTree<'a> {
+    fn new(points: &'a [Coord]) -> Self {
+        let mut indices: Vec<usize> = (0..points.len()).collect();
+        let mut nodes = Vec::with_capacity(points.len());
+        let root = Self::build(points, &mut indices, 0, &mut nodes);
+
+        KdTree { points, nodes, root }
+    }
+
+    fn build(points: &[Coord], indices: &mut [usize], depth: usize, nodes: &mut Vec<KdNode>) -> Option<usize> {
+        if indices.is_empty() {
+            return None;
         }
 
-        let circuit = get_circuit(&key, connections);
+        let axis = |point: &Coord| match depth % 3 {
+            0 => point.0,
+            1 => point.1,
+            _ => point.2,
+        };
 
-        for c in circuit.clone() {
-            connections.remove(&c);
-            seen.insert(c);
+        let (mut min, mut max) = (points[indices[0]], points[indices[0]]);
+        for point in indices.iter().map(|i| points[*i]) {
+            min = (min.0.min(point.0), min.1.min(point.1), min.2.min(point.2));
+            max = (max.0.max(point.0), max.1.max(point.1), max.2.max(point.2));
         }
+        let max_index = *indices.iter().max().unwrap();
 
-        circuits.push(circuit);
+        let middle = indices.len() / 2;
+        indices.select_nth_unstable_by_key(middle, |i| axis(&points[*i]));
+        let point = indices[middle];
+        let (left, rest) = indices.split_at_mut(middle);
+
+        let children = [
+            Self::build(points, left, depth + 1, nodes),
+            Self::build(points, &mut rest[1..], depth + 1, nodes),
+        ];
+
+        nodes.push(KdNode { point, min, max, max_index, children });
+        Some(nodes.len() - 1)
     }
 
-    circuits
+    /// The squared distance from `point` to the nearest spot in the box of
+    /// `node`, which no point below `node` can be closer than.
+    fn bound(&self, point: &Coord, node: usize) -> i128 {
+        let KdNode { min, max, .. } = self.nodes[node];
+        let nearest = (
+            point.0.clamp(min.0, max.0),
+            point.1.clamp(min.1, max.1),
+            point.2.clamp(min.2, max.2),
+        );
+
+        squared_distance(point, &nearest)
+    }
 }
 
-fn part1(points: &HashSet<Coord>) -> usize {
-    // let max_connections = 10;
-    let max_connections = 1000;
+/// An entry in the search heap of `Neighbours`. The variant order matters:
+/// at equal distances nodes come before points, so every point at a given
+/// distance is in the heap before the first of them is taken.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    Node(usize),
+    Point(usize),
+}
 
-    let mut distances = vec![];
-    for point_a in points {
-        for point_b in points {
-            if point_a == point_b {
-                continue;
-            }
+/// The points with a larger index than `point`, nearest first and then by
+/// index, found by a best-first walk down the tree that only opens nodes
+/// once nothing left can be closer.
+struct Neighbours {
+    point: usize,
+    heap: BinaryHeap<Reverse<(i128, Target)>>,
+}
 
-            distances.push((*point_a, *point_b, get_distance(point_a, point_b)));
+impl Neighbours {
+    fn new(tree: &KdTree, point: usize) -> Self {
+        let mut neighbours = Neighbours { point, heap: BinaryHeap::new() };
+        if let Some(root) = tree.root {
+            neighbours.push_node(tree, root);
         }
-    }
 
-    distances.sort_by(|(_, _, d1), (_, _, d2)| {
-        d2.total_cmp(d1)
-    });
+        neighbours
+    }
 
-    let mut connection_count = 0;
-    let mut connections = HashMap::new();
+    fn push_node(&mut self, tree: &KdTree, node: usize) {
+        // Pairs are only produced from their lower index, so subtrees of
+        // lower indices have nothing to offer.
+        if tree.nodes[node].max_index > self.point {
+            let bound = tree.bound(&tree.points[self.point], node);
+            self.heap.push(Reverse((bound, Target::Node(node))));
+        }
+    }
 
-    while connection_count < max_connections {
-        if let Some((point_a, point_b, _)) = distances.pop() {
-            if are_connected(&point_a, &point_b, &connections) {
-                continue;
+    fn next(&mut self, tree: &KdTree) -> Option<(i128, usize)> {
+        while let Some(Reverse((distance, target))) = self.heap.pop() {
+            match target {
+                Target::Point(point) => return Some((distance, point)),
+                Target::Node(node) => {
+                    let KdNode { point, children, .. } = tree.nodes[node];
+                    if point > self.point {
+                        let distance = squared_distance(&tree.points[self.point], &tree.points[point]);
+                        self.heap.push(Reverse((distance, Target::Point(point))));
+                    }
+                    for child in children.into_iter().flatten() {
+                        self.push_node(tree, child);
+                    }
+                }
             }
-            connections
-                .entry(point_a)
-                .and_modify(|connected_to_a| { connected_to_a.insert(point_b); })
-                .or_insert_with(|| HashSet::from([point_b]));
-
-            connections
-                .entry(point_b)
-                .and_modify(|connected_to_b| { connected_to_b.insert(point_a); })
-                .or_insert_with(|| HashSet::from([point_a]));
-
-            connection_count += 1;
-        } else {
-            break;
         }
+
+        None
     }
+}
+
+/// Every pair of point indices `(a, b)` with `a < b`, in the same order as
+/// `pairs_by_distance`, produced lazily by merging each point's stream of
+/// `Neighbours`.
+struct ClosestPairs<'a> {
+    tree: KdTree<'a>,
+    neighbours: Vec<Neighbours>,
+    /// The next pair from each point whose stream is not used up.
+    heap: BinaryHeap<Reverse<(i128, usize, usize)>>,
+}
 
-    let mut circuits = find_circuits(&mut connections);
+impl<'a> ClosestPairs<'a> {
+    fn new(points: &'a [Coord]) -> Self {
+        let tree = KdTree::new(points);
+        let mut neighbours: Vec<Neighbours> = (0..points.len()).map(|point| Neighbours::new(&tree, point)).collect();
 
-    circuits.sort_by(|c1, c2| {
-        (c2.len()).cmp(&c1.len())
-    });
+        let heap = neighbours
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(a, neighbours)| neighbours.next(&tree).map(|(distance, b)| Reverse((distance, a, b))))
+            .collect();
 
-    circuits.iter().take(3).map(|circuit| circuit.len()).product()
+        ClosestPairs { tree, neighbours, heap }
+    }
 }
 
-fn part2(points: &HashSet<Coord>) -> i64 {
-    let mut connections = HashMap::new();
+impl Iterator for ClosestPairs<'_> {
+    type Item = (usize, usize);
 
-    let mut distances = vec![];
-    for point_a in points {
-        for point_b in points {
-            if point_a == point_b {
-                continue;
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, a, b)) = self.heap.pop()?;
 
-            distances.push((*point_a, *point_b, get_distance(point_a, point_b)));
+        if let Some((distance, next)) = self.neighbours[a].next(&self.tree) {
+            self.heap.push(Reverse((distance, a, next)));
         }
+
+        Some((a, b))
     }
+}
 
-    distances.sort_by(|(_, _, d1), (_, _, d2)| {
-        d2.total_cmp(d1)
-    });
+/// The circuits after connecting the `connections` closest pairs, whether or
+/// not a pair was already in the same circuit.
+fn connect(points: &[Coord], connections: usize) -> Circuits {
+    let mut circuits = Circuits::new(points.len());
+    for (a, b) in ClosestPairs::new(points).take(connections) {
+        circuits.connect(a, b);
+    }
 
-    while let Some((point_a, point_b, _)) = distances.pop() {
-        if are_connected(&point_a, &point_b, &connections) {
-            continue;
-        }
+    circuits
+}
 
-        connections
-            .entry(point_a)
-            .and_modify(|connected_to_a| { connected_to_a.insert(point_b); })
-            .or_insert_with(|| HashSet::from([point_b]));
+fn part1(points: &[Coord], connections: usize) -> usize {
+    connect(points, connections).sizes().iter().take(3).product()
+}
 
-        connections
-            .entry(point_b)
-            .and_modify(|connected_to_b| { connected_to_b.insert(point_a); })
-            .or_insert_with(|| HashSet::from([point_a]));
+/// Builds the minimum spanning tree with Kruskal's algorithm, taking pairs
+/// only until the last connection.
+fn part2(points: &[Coord]) -> i64 {
+    let mut circuits = Circuits::new(points.len());
 
-        let circuit = get_circuit(&point_a, &connections);
-        if circuit.len() == points.len() {
-            return point_a.0 * point_b.0;
-        };
+    for (a, b) in ClosestPairs::new(points) {
+        if circuits.connect(a, b) && circuits.count == 1 {
+            return points[a].0 * points[b].0;
+        }
     }
 
     unreachable!()
 }
+
+struct Options {
+    input: Option<String>,
+    /// Use 10 for the example.
+    connections: usize,
+    sizes: Option<usize>,
+    verify: bool,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        input: None,
+        connections: 1000,
+        sizes: None,
+        verify: false,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| panic!("Expected a value after '{arg}'"));
+
+        match arg.as_str() {
+            "--input" => options.input = Some(value()),
+            "--connections" => options.connections = value().parse().expect("Expected a number of connections"),
+            "--sizes" => options.sizes = Some(value().parse().expect("Expected a number of connections")),
+            "--verify" => options.verify = true,
+            _ => panic!("Unknown argument '{arg}'"),
+        }
+    }
+
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn assert_same_pairs(points: &[Coord]) {
+        let brute_force = pairs_by_distance(points);
+        let tree: Vec<(usize, usize)> = ClosestPairs::new(points).collect();
+        assert_eq!(tree, brute_force);
+    }
+
+    fn sorted(mut points: Vec<Coord>) -> Vec<Coord> {
+        points.sort_unstable();
+        points.dedup();
+        points
+    }
+
+    #[test]
+    fn tiny_point_sets() {
+        assert_same_pairs(&[]);
+        assert_same_pairs(&[(1, 2, 3)]);
+        assert_same_pairs(&[(1, 2, 3), (4, 5, 6)]);
+    }
+
+    #[test]
+    fn random_points_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(8);
+
+        for (count, range) in [(10, 5), (50, 1000), (200, 100_000), (300, 20)] {
+            let mut coordinate = || rng.gen_range(-range, range);
+            let points = sorted((0..count).map(|_| (coordinate(), coordinate(), coordinate())).collect());
+            assert_same_pairs(&points);
+        }
+    }
+
+    #[test]
+    fn equal_distance_ties_match_brute_force() {
+        let lattice = sorted((0..6).flat_map(|x| (0..6).flat_map(move |y| (0..6).map(move |z| (x, y, z)))).collect());
+        assert_same_pairs(&lattice);
+
+        // Every pair at the same distance except for one closer pair.
+        let square = sorted(vec![(0, 0, 0), (10, 0, 0), (0, 10, 0), (10, 10, 0), (0, 0, 10), (10, 0, 10), (5, 5, 5)]);
+        assert_same_pairs(&square);
+    }
+
+    #[test]
+    fn ties_do_not_depend_on_input_order() {
+        let lines: Vec<String> = (0..4)
+            .flat_map(|x| (0..4).flat_map(move |y| (0..4).map(move |z| format!("{},{},{}", x * 3, y * 3, z * 3))))
+            .collect();
+        let mut reversed = lines.clone();
+        reversed.reverse();
+
+        let (points, reversed) = (parse(&lines), parse(&reversed));
+        assert_eq!(points, reversed);
+        assert_eq!(part1(&points, 40), part1(&reversed, 40));
+        assert_eq!(part2(&points), part2(&reversed));
+    }
+
+    #[test]
+    fn large_coordinates_keep_exact_order() {
+        let big = COORD_LIMIT - 1;
+        let points = sorted(vec![(-big, -big, -big), (big, big, big), (big, big, big - 1), (0, 0, 0), (big, -big, 0)]);
+        assert_same_pairs(&points);
+    }
+}